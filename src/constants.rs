@@ -1,20 +1,27 @@
 // CPU Constants
-pub const OPCODE_SIZE: usize = 1;
-pub const RAM_SIZE: usize = 0xFFFF;
+pub const OPCODE_SIZE: usize = 1; // Length in bytes of an instruction with no data
+pub const RAM_SIZE: usize = 0x1_0000;
+pub const DEFAULT_SAFETY_LIMIT: usize = 50_000_000; // Ticks an unbounded run may take
+
+// The window of memory randomized along with the registers for fuzzing
+pub const FUZZ_RAM_START: usize = 0x2000;
+pub const FUZZ_RAM_END: usize = 0x2100;
 
 // Flags and their order/bitmasks
 // S - Sign Flag
 // Z - Zero Flag
-// 0 - Not used, always zero
+// 0 - Not used, always zero (X5 on the 8085)
 // A - also called AC, Auxiliary Carry Flag
 // 0 - Not used, always zero
 // P - Parity Flag
-// 1 - Not used, always one
+// 1 - Not used, always one (V on the 8085)
 // C - Carry Flag
 pub const FLAG_SIGN: u8 = 0b1000_0000;
 pub const FLAG_ZERO: u8 = 0b0100_0000;
+pub const FLAG_X5: u8 = 0b0010_0000; // 8085 only
 pub const FLAG_AUXCARRY: u8 = 0b0001_0000;
 pub const FLAG_PARITY: u8 = 0b0000_0100;
+pub const FLAG_OVERFLOW: u8 = 0b0000_0010; // 8085 only
 pub const FLAG_CARRY: u8 = 0b0000_0001;
 
 // Window and display concerns
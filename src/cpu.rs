@@ -1,8 +1,13 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::unused_self, clippy::cast_possible_truncation)]
+use std::collections::VecDeque;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::time::Instant;
+
+#[cfg(feature = "fuzz")]
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 pub use crate::constants::*;
 pub use crate::utils::*;
@@ -14,6 +19,126 @@ pub enum ProgramCounter {
     Jump(usize), // The operation jumps to a point in memory
 }
 
+impl ProgramCounter {
+    // Where PC ends up from pc: on by the instruction's length in bytes for
+    // Next, Two and Three, or the destination for Jump. Wraps to 16 bits.
+    #[must_use]
+    pub fn advance(&self, pc: usize) -> usize {
+        let next = match self {
+            ProgramCounter::Next => pc + 1,
+            ProgramCounter::Two => pc + 2,
+            ProgramCounter::Three => pc + 3,
+            ProgramCounter::Jump(d) => *d,
+        };
+        next & 0xFFFF
+    }
+}
+
+// What to do when run_opcode comes across an opcode it does not know
+#[derive(Clone, Copy, PartialEq)]
+pub enum UnknownPolicy {
+    Error,     // Stop with an error (the default)
+    NopAndLog, // Treat it as a NOP and record where it was found
+}
+
+// Which processor to behave as; the 8085 adds RIM and SIM on 0x20 and 0x30
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuMode {
+    I8080, // Strict 8080 (the default)
+    I8085, // 8085 compatible
+}
+
+// Errors the CPU can run into while executing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuError {
+    UnknownOpcode(u8), // The opcode is not implemented
+    InvalidVector(u8), // An RST vector outside of 0..=7
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode(op) => {
+                write!(f, "!! OPCODE: {:#04X} {:#010b} is unknown !!", op, op)
+            }
+            CpuError::InvalidVector(loc) => {
+                write!(f, "!! RST: vector {} is out of range (0..=7) !!", loc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+// Lets callers which still deal in String errors keep using ?
+impl From<CpuError> for String {
+    fn from(e: CpuError) -> String {
+        e.to_string()
+    }
+}
+
+// Why a run of the CPU came to a stop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunStopReason {
+    Halted,                 // A HLT was executed
+    BudgetExhausted,        // All of the cycles asked for have been run
+    SafetyLimit,            // An unbounded run hit the safety limit
+    StackError { sp: u16 }, // SP rose above the stack baseline (more popped than pushed)
+    // A watched address was changed to its target value
+    ValueBreakpoint { addr: u16, value: u8 },
+    Error(CpuError), // A tick failed; the run also returns the error itself
+}
+
+// Where and when the last run of the CPU stopped, and why
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StopContext {
+    pub reason: RunStopReason,
+    pub pc: u16,       // The instruction the next tick would run
+    pub cycles: usize, // cycle_count at the stop
+}
+
+// A copy of the programmer visible registers at one point in time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegistersSnapshot {
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub flags: u8,
+}
+
+impl RegistersSnapshot {
+    // One line in the format of the classic 8080 monitors, e.g.
+    // A=42 BC=0000 DE=0000 HL=2000 SP=23FF PC=0100 F=SZ-A-P-C
+    // with a '-' for each clear flag and for the unused bits
+    #[must_use]
+    pub fn classic_line(&self) -> String {
+        let flag = |mask: u8, c: char| if self.flags & mask == 0 { '-' } else { c };
+        let f: String = [
+            flag(super::FLAG_SIGN, 'S'),
+            flag(super::FLAG_ZERO, 'Z'),
+            '-',
+            flag(super::FLAG_AUXCARRY, 'A'),
+            '-',
+            flag(super::FLAG_PARITY, 'P'),
+            '-',
+            flag(super::FLAG_CARRY, 'C'),
+        ]
+        .iter()
+        .collect();
+
+        format!(
+            "A={:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X} F={}",
+            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.pc, f
+        )
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum Registers {
     A,
@@ -73,9 +198,28 @@ pub struct Cpu {
     // A flag to indicate that we do not wish to execute, probably just printing disassembly
     pub nop: bool,
 
-    pub cycle_count: usize,        // Cycle count
+    pub cycle_count: usize, // Cycle count; saturates rather than wrapping
+    pub last_cycles: usize, // Clock states taken by the last instruction
     pub last_opcode: (u8, u8, u8), // Just a record of the last opcode.
     pub next_opcode: (u8, u8, u8), // Next opcode we are running.
+
+    pub unknown_policy: UnknownPolicy, // How to handle unknown opcodes
+    pub cpu_mode: CpuMode,             // 8080, or 8085 with RIM/SIM
+    interrupt_mask: u8,                // 8085 RST 5.5/6.5/7.5 mask bits set by SIM
+    unknown_opcodes: Vec<(u16, u8)>,   // (pc, opcode) of each unknown skipped over
+    rom_len: usize,                    // Bytes loaded by the last load_rom
+    safety_limit: usize,               // Most ticks an unbounded run_blocking will take
+    cycle_budget_remaining: Option<usize>, // Ticks left in the current (or last) run
+    stack_baseline: Option<u16>,       // When set, runs stop if SP pops past this
+    stop_context: Option<StopContext>, // Why the last run stopped
+    value_breakpoints: Vec<(u16, u8)>, // (addr, target) pairs runs stop on
+    last_branch_taken: bool,           // The last instruction didn't just fall through
+    last_compare: Option<(u8, u8)>,    // (A, operand) if the last instruction was CMP/CPI
+    live_disassembly: VecDeque<String>, // The latest lines for instructions run
+    live_depth: usize,                 // Most lines kept; 0 when off
+    in_default: u8,                    // What IN reads, with no device on the port
+    interrupts_serviced: u64,          // Interrupts entered since the CPU was made
+    executed: Vec<bool>,               // Addresses run so far, when recording
 }
 
 impl Default for Cpu {
@@ -88,12 +232,56 @@ impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "CYCLES:{:#08X} PC:{:#06X} SP:{:#06X}\nA:{:#06X}\nB:{:#04X} C:{:#04X}\nD:{:#04X} E:{:#04X}\nH:{:#04X} L:{:#04X}\nsp $[{:#06X}]={:#04X} sp+1 $[{:06X}]={:#04X}",
-            self.cycle_count, self.pc, self.sp, self.a, self.b, self.c, self.d, self.e, self.h, self.l,self.sp,self.memory[usize::from(self.sp)],self.sp+1,self.memory[usize::from(self.sp+1)]
+            "CYCLES:{:#08X} PC:{:#06X} SP:{:#06X}\nA:{:#06X}\nB:{:#04X} C:{:#04X}\nD:{:#04X} E:{:#04X}\nH:{:#04X} L:{:#04X}\nsp $[{:#06X}]={:#04X} sp+1 $[{:#06X}]={:#04X}",
+            self.cycle_count, self.pc, self.sp, self.a, self.b, self.c, self.d, self.e, self.h, self.l,self.sp,self.memory[usize::from(self.sp)],self.sp.wrapping_add(1),self.memory[usize::from(self.sp.wrapping_add(1))]
         )
     }
 }
 
+// Debug output summarizes memory as its size and the ranges holding non-zero
+// bytes, rather than dumping all of it.
+impl fmt::Debug for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut regions: Vec<String> = Vec::new();
+        let mut start: Option<usize> = None;
+        for (i, &b) in self.memory.iter().enumerate() {
+            match (b != 0, start) {
+                (true, None) => start = Some(i),
+                (false, Some(s)) => {
+                    regions.push(format!("{:#06X}..{:#06X}", s, i));
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(s) = start {
+            regions.push(format!("{:#06X}..{:#06X}", s, self.memory.len()));
+        }
+
+        f.debug_struct("Cpu")
+            .field("pc", &format_args!("{:#06X}", self.pc))
+            .field("sp", &format_args!("{:#06X}", self.sp))
+            .field(
+                "regs",
+                &format_args!(
+                    "A:{:#04X} B:{:#04X} C:{:#04X} D:{:#04X} E:{:#04X} H:{:#04X} L:{:#04X}",
+                    self.a, self.b, self.c, self.d, self.e, self.h, self.l
+                ),
+            )
+            .field("flags", &format_args!("{:08b}", self.flags))
+            .field("cycle_count", &self.cycle_count)
+            .field(
+                "memory",
+                &format_args!(
+                    "{} bytes, non-zero: [{}]",
+                    self.memory.len(),
+                    regions.join(", ")
+                ),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
 impl Cpu {
     #[must_use]
     pub fn new() -> Cpu {
@@ -112,8 +300,26 @@ impl Cpu {
             disassemble: false,
             nop: false,
             cycle_count: 0x00,
+            last_cycles: 0,
             last_opcode: (0, 0, 0),
             next_opcode: (0, 0, 0),
+            unknown_policy: UnknownPolicy::Error,
+            cpu_mode: CpuMode::I8080,
+            interrupt_mask: 0,
+            unknown_opcodes: Vec::new(),
+            rom_len: 0,
+            safety_limit: DEFAULT_SAFETY_LIMIT,
+            cycle_budget_remaining: None,
+            stack_baseline: None,
+            stop_context: None,
+            value_breakpoints: Vec::new(),
+            last_branch_taken: false,
+            last_compare: None,
+            live_disassembly: VecDeque::new(),
+            live_depth: 0,
+            in_default: 0xFF,
+            interrupts_serviced: 0,
+            executed: Vec::new(),
         }
     }
 
@@ -252,6 +458,126 @@ impl Cpu {
         };
     }
 
+    // Sets the 8085 V (signed overflow) and X5 (V xor S) flags for lhs + rhs,
+    // or lhs - rhs when subtract is set, giving res. rhs is the operand alone,
+    // without any carry or borrow in: V comes from the signs of the operands
+    // and the result, which the carry can't change. The 8080 has no such
+    // flags, so in that mode bits 1 and 5 are left alone.
+    fn update_8085_flags(&mut self, lhs: u8, rhs: u8, res: u8, subtract: bool) {
+        if self.cpu_mode != CpuMode::I8085 {
+            return;
+        }
+
+        // A subtract adds the complement, so overflow is worked out against !rhs
+        let rhs = if subtract { !rhs } else { rhs };
+        let overflow = !(lhs ^ rhs) & (lhs ^ res) & 0x80 != 0;
+        if overflow {
+            self.set_flag(super::FLAG_OVERFLOW);
+        } else {
+            self.reset_flag(super::FLAG_OVERFLOW);
+        }
+        if overflow ^ get_sign(res) {
+            self.set_flag(super::FLAG_X5);
+        } else {
+            self.reset_flag(super::FLAG_X5);
+        }
+    }
+
+    // On the 8085 INX and DCX set X5 when the pair wraps and clear it otherwise
+    fn update_x5_16(&mut self, wrapped: bool) {
+        if self.cpu_mode != CpuMode::I8085 {
+            return;
+        }
+        if wrapped {
+            self.set_flag(super::FLAG_X5);
+        } else {
+            self.reset_flag(super::FLAG_X5);
+        }
+    }
+
+    // Captures the current registers, PC, SP and flags
+    #[must_use]
+    pub fn snapshot(&self) -> RegistersSnapshot {
+        RegistersSnapshot {
+            pc: self.pc as u16,
+            sp: self.sp,
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            flags: self.flags,
+        }
+    }
+
+    // Writes every register, PC, SP and the flags back from a snapshot; the
+    // inverse of ``snapshot``
+    pub fn load_registers(&mut self, snap: &RegistersSnapshot) {
+        self.pc = usize::from(snap.pc);
+        self.sp = snap.sp;
+        self.apply_state(
+            [snap.a, snap.b, snap.c, snap.d, snap.e, snap.h, snap.l],
+            snap.flags,
+        );
+    }
+
+    // Reads the little endian word at addr (low byte first), wrapping past
+    // 0xFFFF, the way the 8080 stores pointers
+    #[must_use]
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.memory[usize::from(addr)];
+        let hi = self.memory[usize::from(addr.wrapping_add(1))];
+        u16::from(hi) << 8 | u16::from(lo)
+    }
+
+    // Writes value to addr as a little endian word, wrapping past 0xFFFF
+    pub fn poke_u16(&mut self, addr: u16, value: u16) {
+        self.memory[usize::from(addr)] = (value & 0xFF) as u8;
+        self.memory[usize::from(addr.wrapping_add(1))] = (value >> 8) as u8;
+    }
+
+    // Returns the next depth bytes of the stack, from SP upward, as (address, value)
+    #[must_use]
+    pub fn stack_view(&self, depth: usize) -> Vec<(u16, u8)> {
+        (0..depth)
+            .map(|i| {
+                let addr = self.sp.wrapping_add(i as u16);
+                (addr, self.memory[usize::from(addr)])
+            })
+            .collect()
+    }
+
+    // Sets A, B, C, D, E, H, L (in that order) and the flags in one go
+    pub fn apply_state(&mut self, regs: [u8; 7], flags: u8) {
+        self.a = regs[0];
+        self.b = regs[1];
+        self.c = regs[2];
+        self.d = regs[3];
+        self.e = regs[4];
+        self.h = regs[5];
+        self.l = regs[6];
+        self.flags = flags;
+    }
+
+    /// Puts the CPU into a reproducible random state for fuzzing: every
+    /// register, SP, the flags, and the memory between `FUZZ_RAM_START` and
+    /// `FUZZ_RAM_END` are filled from a PRNG seeded with `seed`.  The
+    /// flag bits which are fixed on the 8080 keep their fixed values.
+    #[cfg(feature = "fuzz")]
+    pub fn randomize_from_seed(&mut self, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut regs = [0_u8; 7];
+        rng.fill(&mut regs);
+        let flags: u8 = rng.gen();
+        self.apply_state(regs, (flags & 0b1101_0101) | 0b0000_0010);
+        self.sp = rng.gen();
+
+        rng.fill(&mut self.memory[FUZZ_RAM_START..FUZZ_RAM_END]);
+    }
+
     pub fn set_disassemble(&mut self, d: bool) {
         self.disassemble = d;
     }
@@ -260,6 +586,228 @@ impl Cpu {
         self.nop = n;
     }
 
+    // Returns the ticks left in the budget of the current run, or of the last run
+    // once it has stopped. None until a budgeted run has been started.
+    #[must_use]
+    pub fn cycle_budget_remaining(&self) -> Option<usize> {
+        self.cycle_budget_remaining
+    }
+
+    // Returns where the last run_quantum or run_blocking stopped and why.
+    // None until a run has stopped.
+    #[must_use]
+    pub fn stop_context(&self) -> Option<StopContext> {
+        self.stop_context
+    }
+
+    // Keeps the details of a run stopping for reason, and hands it back
+    fn record_stop(&mut self, reason: RunStopReason) -> RunStopReason {
+        self.stop_context = Some(StopContext {
+            reason,
+            pc: self.pc as u16,
+            cycles: self.cycle_count,
+        });
+        reason
+    }
+
+    // Runs stop with ValueBreakpoint as soon as an instruction changes the
+    // byte at addr to target
+    pub fn add_value_breakpoint(&mut self, addr: u16, target: u8) {
+        self.value_breakpoints.push((addr, target));
+    }
+
+    pub fn clear_value_breakpoints(&mut self) {
+        self.value_breakpoints.clear();
+    }
+
+    // When on, runs stop with StackError as soon as a RET or POP takes more off
+    // the stack than was put on since now. Depth is counted with wrapping, so
+    // a stack left at the reset SP of 0x0000 works too. LXI SP and SPHL set a
+    // new starting point instead.
+    pub fn set_break_on_stack_underflow(&mut self, on: bool) {
+        self.stack_baseline = if on { Some(self.sp) } else { None };
+    }
+
+    // With a depth above 0, every instruction ticked is disassembled as it
+    // runs, with its operand, and the latest depth lines are kept until taken
+    // with take_live_disassembly; older ones are dropped, so a long run
+    // that never drains the buffer doesn't grow it. A depth of 0 turns it off.
+    // Unlike the disassemble flag nothing is printed; the caller decides
+    // where it goes.
+    pub fn set_live_disassemble(&mut self, depth: usize) {
+        self.live_depth = depth;
+        let excess = self.live_disassembly.len().saturating_sub(depth);
+        self.live_disassembly.drain(..excess);
+    }
+
+    // Hands over the lines kept since the last call, oldest first
+    pub fn take_live_disassembly(&mut self) -> Vec<String> {
+        self.live_disassembly.drain(..).collect()
+    }
+
+    // When on, every address an instruction is run from gets marked, for
+    // finding dead code. Turning it on starts a fresh record; off drops it.
+    pub fn set_record_execution(&mut self, on: bool) {
+        self.executed = if on {
+            vec![false; RAM_SIZE]
+        } else {
+            Vec::new()
+        };
+    }
+
+    // Returns which addresses have had an instruction run from them since
+    // recording was turned on. Empty when not recording.
+    #[must_use]
+    pub fn executed_addresses(&self) -> &[bool] {
+        &self.executed
+    }
+
+    // A helper for debuggers, not a hardware register: after a CMP or CPI
+    // (the last instruction run), how A compared to the operand as signed
+    // two's complement bytes. The 8080 has no overflow flag, so this can't come
+    // from the flags; the operands are kept as the compare runs. None if the
+    // last instruction wasn't a compare.
+    #[must_use]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn last_compare_ordering(&self) -> Option<std::cmp::Ordering> {
+        self.last_compare
+            .map(|(a, operand)| (a as i8).cmp(&(operand as i8)))
+    }
+
+    // True if the last instruction (or interrupt) left PC somewhere other
+    // than the next instruction in line: a taken jump, call or return
+    #[must_use]
+    pub fn last_branch_taken(&self) -> bool {
+        self.last_branch_taken
+    }
+
+    // Returns how many interrupts have been serviced since the CPU was made
+    #[must_use]
+    pub fn interrupts_serviced(&self) -> u64 {
+        self.interrupts_serviced
+    }
+
+    // Sets the value IN reads from a port; with no devices attached this is
+    // every port. Defaults to 0xFF, as an undriven data bus reads.
+    pub fn set_in_default(&mut self, value: u8) {
+        self.in_default = value;
+    }
+
+    pub fn set_safety_limit(&mut self, limit: usize) {
+        self.safety_limit = limit;
+    }
+
+    pub fn set_cpu_mode(&mut self, m: CpuMode) {
+        self.cpu_mode = m;
+    }
+
+    pub fn set_unknown_opcode_policy(&mut self, p: UnknownPolicy) {
+        self.unknown_policy = p;
+    }
+
+    // Returns the (pc, opcode) of each unknown opcode skipped while in NopAndLog
+    // mode, once each, in the order they were first found
+    #[must_use]
+    pub fn unknown_opcodes(&self) -> &[(u16, u8)] {
+        &self.unknown_opcodes
+    }
+
+    // Returns true once a ROM with at least one byte in it has been loaded
+    #[must_use]
+    pub fn is_rom_loaded(&self) -> bool {
+        self.rom_len > 0
+    }
+
+    // Returns the number of bytes loaded by the last call to load_rom
+    #[must_use]
+    pub fn rom_len(&self) -> usize {
+        self.rom_len
+    }
+
+    /// Loads one of the canned programs from ``testroms`` at 0x0000, by name
+    ///
+    /// # Errors
+    /// Will return an error if there is no test ROM with that name
+    pub fn load_test_rom(&mut self, name: &str) -> Result<(), String> {
+        let rom =
+            crate::testroms::by_name(name).ok_or_else(|| format!("Unknown test rom: {}", name))?;
+        self.memory[..rom.len()].copy_from_slice(rom);
+        self.rom_len = rom.len();
+
+        Ok(())
+    }
+
+    /// Loads each (name, address, bytes) segment of a manifest at its address,
+    /// as read by ``manifest::read``
+    ///
+    /// # Errors
+    /// Will return an error naming the first segment which doesn't fit in
+    /// memory; the segments before it are left loaded
+    pub fn load_manifest(&mut self, manifest: &[(String, usize, Vec<u8>)]) -> Result<(), String> {
+        for (name, addr, bytes) in manifest {
+            let end = addr
+                .checked_add(bytes.len())
+                .filter(|&end| end <= self.memory.len())
+                .ok_or_else(|| {
+                    format!(
+                        "Segment {} at {:#06X} runs past the end of memory",
+                        name, addr
+                    )
+                })?;
+            self.memory[*addr..end].copy_from_slice(bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Guesses where a raw binary should be loaded and started from:
+    ///
+    /// - A ROM whose first byte is 0xC3 (JMP) is a plain ROM at 0x0000.
+    /// - Otherwise, one that calls BDOS with ``CALL 0x0005`` within its first
+    ///   16 bytes, as a CP/M .COM program setting up to print does, goes at
+    ///   0x0100.
+    /// - Anything else is a plain ROM at 0x0000.
+    ///
+    /// Only the start is looked at, so data later in a ROM can't sway the
+    /// guess.  A .COM that opens with a JMP is taken as a plain ROM; give
+    /// ``load_rom_autostart`` the start for those.
+    #[must_use]
+    pub fn detect_start(rom: &[u8]) -> usize {
+        if rom.first() == Some(&0xC3) {
+            return 0x0000;
+        }
+
+        let head = &rom[..rom.len().min(16)];
+        if head.windows(3).any(|w| w == [0xCD, 0x05, 0x00]) {
+            0x0100
+        } else {
+            0x0000
+        }
+    }
+
+    /// Loads ``rom`` at ``start``, or where ``detect_start`` guesses if that's
+    /// None, and points PC at it.  Returns the address used.
+    ///
+    /// # Errors
+    /// Will return an error, leaving memory and PC alone, if the ROM doesn't
+    /// fit in memory from that address
+    pub fn load_rom_autostart(
+        &mut self,
+        rom: &[u8],
+        start: Option<usize>,
+    ) -> Result<usize, String> {
+        let start = start.unwrap_or_else(|| Cpu::detect_start(rom));
+        let end = start
+            .checked_add(rom.len())
+            .filter(|&end| end <= self.memory.len())
+            .ok_or_else(|| format!("ROM at {:#06X} runs past the end of memory", start))?;
+        self.memory[start..end].copy_from_slice(rom);
+        self.rom_len = rom.len();
+        self.pc = start;
+
+        Ok(start)
+    }
+
     /// Load the ROM file into memory, starting at ``start_index``
     /// Returns a tuple containing the index we started at and where we
     /// actually finished at.
@@ -275,9 +823,11 @@ impl Cpu {
     ) -> Result<(usize, usize), std::io::Error> {
         let rom = File::open(file)?;
         let mut last_idx: usize = 0;
+        self.rom_len = 0;
         for (i, b) in rom.bytes().enumerate() {
             self.memory[start_index + i] = b.unwrap();
             last_idx = i;
+            self.rom_len = i + 1;
         }
         Ok((start_index, start_index + last_idx + 1))
     }
@@ -291,7 +841,7 @@ impl Cpu {
     /// Will return an error if necessary
     /// # Panics
     /// Will panic if an error happens
-    pub fn tick(&mut self) -> Result<usize, String> {
+    pub fn tick(&mut self) -> Result<usize, CpuError> {
         let opcode = self.read_opcode();
         self.last_opcode = opcode;
         let this_pc = self.pc;
@@ -302,10 +852,27 @@ impl Cpu {
             return Ok(this_pc);
         }
 
-        self.cycle_count += 1;
+        self.cycle_count = self.cycle_count.saturating_add(1);
+
+        // Disassembled before running, while the bytes are still the ones run
+        let live_line = if self.live_depth > 0 {
+            Some(crate::disassembler::line_at(self, this_pc).to_string())
+        } else {
+            None
+        };
 
         match self.run_opcode(opcode) {
             Ok(_) => {
+                if let Some(line) = live_line {
+                    if self.live_disassembly.len() == self.live_depth {
+                        self.live_disassembly.pop_front();
+                    }
+                    self.live_disassembly.push_back(line);
+                }
+                if let Some(e) = self.executed.get_mut(this_pc) {
+                    *e = true;
+                }
+                self.last_cycles = crate::disassembler::get_opcode_cycles(opcode.0);
                 self.next_opcode = self.read_opcode();
                 Ok(this_pc)
             }
@@ -313,35 +880,244 @@ impl Cpu {
         }
     }
 
+    /// Runs at most ``max_cycles`` ticks and then returns, so a caller that owns
+    /// its own main loop (such as a GUI) can get back to its events in between.
+    /// Calling it again simply carries on from where the last call stopped.
+    ///
+    /// # Errors
+    /// Will return an error if any of the ticks fail
+    pub fn run_quantum(&mut self, max_cycles: usize) -> Result<RunStopReason, CpuError> {
+        self.run_quantum_with(max_cycles, |_| ())
+    }
+
+    /// As ``run_quantum``, but calls ``on_step`` with the CPU after every tick,
+    /// so a caller can watch the run (and the budget left) as it happens.
+    ///
+    /// # Errors
+    /// Will return an error if any of the ticks fail
+    pub fn run_quantum_with<F>(
+        &mut self,
+        max_cycles: usize,
+        mut on_step: F,
+    ) -> Result<RunStopReason, CpuError>
+    where
+        F: FnMut(&Cpu),
+    {
+        self.cycle_budget_remaining = Some(max_cycles);
+        for remaining in (0..max_cycles).rev() {
+            if self.nop {
+                return Ok(self.record_stop(RunStopReason::Halted));
+            }
+            let before: Vec<u8> = self
+                .value_breakpoints
+                .iter()
+                .map(|&(addr, _)| self.memory[usize::from(addr)])
+                .collect();
+            if let Err(e) = self.tick() {
+                self.record_stop(RunStopReason::Error(e));
+                return Err(e);
+            }
+            self.cycle_budget_remaining = Some(remaining);
+            on_step(self);
+
+            let hit = self
+                .value_breakpoints
+                .iter()
+                .zip(before)
+                .find(|&(&(addr, target), was)| {
+                    was != target && self.memory[usize::from(addr)] == target
+                })
+                .map(|(&(addr, value), _)| RunStopReason::ValueBreakpoint { addr, value });
+            if let Some(reason) = hit {
+                return Ok(self.record_stop(reason));
+            }
+
+            if let Some(base) = self.stack_baseline {
+                let op = self.last_opcode.0;
+                if op == 0x31 || op == 0xF9 {
+                    // LXI SP or SPHL moved the stack on purpose; measure from there
+                    self.stack_baseline = Some(self.sp);
+                } else if (matches!(op, 0xC1 | 0xD1 | 0xE1 | 0xF1)
+                    || crate::disassembler::is_return(op))
+                    && stack_depth(base, self.sp) < 0
+                {
+                    let reason = RunStopReason::StackError { sp: self.sp };
+                    return Ok(self.record_stop(reason));
+                }
+            }
+        }
+
+        if self.nop {
+            Ok(self.record_stop(RunStopReason::Halted))
+        } else {
+            Ok(self.record_stop(RunStopReason::BudgetExhausted))
+        }
+    }
+
+    /// Loads the ``BENCHMARK`` test ROM, runs it for ``cycles`` ticks and returns
+    /// the instructions per second achieved.  Only the run itself is timed, not
+    /// the loading.  Returns 0.0 if the run fails or is too quick to measure.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn run_for_benchmark(&mut self, cycles: usize) -> f64 {
+        if self.load_test_rom("BENCHMARK").is_err() {
+            return 0.0;
+        }
+        self.pc = 0;
+        self.set_nop(false);
+        let start_count = self.cycle_count;
+
+        let start = Instant::now();
+        if self.run_quantum(cycles).is_err() {
+            return 0.0;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let executed = self.cycle_count.saturating_sub(start_count) as f64;
+        if elapsed > 0.0 {
+            executed / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Runs until HLT or until ``budget`` ticks have been run.  With no budget
+    /// the run is capped at the safety limit instead, so a ROM stuck in a loop
+    /// can't hang the caller; hitting that cap returns ``SafetyLimit``.
+    ///
+    /// # Errors
+    /// Will return an error if any of the ticks fail
+    pub fn run_blocking(&mut self, budget: Option<usize>) -> Result<RunStopReason, CpuError> {
+        match budget {
+            Some(b) => self.run_quantum(b),
+            None => match self.run_quantum(self.safety_limit)? {
+                RunStopReason::BudgetExhausted => Ok(self.record_stop(RunStopReason::SafetyLimit)),
+                r => Ok(r),
+            },
+        }
+    }
+
+    // Runs for at most max_cycles ticks, stopping just after the first OUT to
+    // port. Returns the value written, which is A. None if the run halted, ran
+    // out or failed before any such OUT.
+    pub fn run_until_port_write(&mut self, port: u8, max_cycles: usize) -> Option<u8> {
+        for _ in 0..max_cycles {
+            if self.nop {
+                return None;
+            }
+            self.tick().ok()?;
+            if let (0xD3, p, _) = self.last_opcode {
+                if p == port {
+                    return Some(self.a);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Runs for at most max_cycles ticks, stopping at the first opcode the CPU
+    // doesn't know, whatever the unknown opcode policy. Returns the PC and the
+    // opcode, leaving PC on it. None if the run halted, ran out or failed
+    // some other way first.
+    pub fn run_until_unknown(&mut self, max_cycles: usize) -> Option<(u16, u8)> {
+        let policy = self.unknown_policy;
+        self.unknown_policy = UnknownPolicy::Error;
+
+        let mut found = None;
+        for _ in 0..max_cycles {
+            if self.nop {
+                break;
+            }
+            match self.tick() {
+                Ok(_) => (),
+                Err(CpuError::UnknownOpcode(op)) => {
+                    found = Some((self.pc as u16, op));
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        self.unknown_policy = policy;
+        found
+    }
+
     // Reads an instruction at ProgramCounter
     // Returns the following two bytes as potential "data" for the instruction.
     // If the two bytes are out of range they will return 0x00
-    pub fn read_opcode(&mut self) -> (u8, u8, u8) {
-        let o = match self.memory.get(self.pc) {
-            Some(&v) => v,
-            None => 0,
-        };
-        let x = match self.memory.get(self.pc + 1) {
-            Some(&v) => v,
-            None => 0,
-        };
-        let y = match self.memory.get(self.pc + 2) {
+    #[must_use]
+    pub fn read_opcode(&self) -> (u8, u8, u8) {
+        self.decode_at(self.pc).0
+    }
+
+    // Decodes the instruction at pc without changing any CPU state.
+    // Returns the same triple as read_opcode, along with only the bytes the
+    // instruction actually uses (opcode first). Operand fetches wrap past
+    // 0xFFFF back around to 0x0000.
+    #[must_use]
+    pub fn decode_at(&self, pc: usize) -> ((u8, u8, u8), Vec<u8>) {
+        let byte = |a: usize| match self.memory.get(a & 0xFFFF) {
             Some(&v) => v,
             None => 0,
         };
-        (o, x, y)
+        let opcode = (byte(pc), byte(pc + 1), byte(pc + 2));
+        let size = crate::disassembler::get_opcode_size(opcode.0);
+
+        (opcode, (pc..pc + size).map(byte).collect())
+    }
+
+    // Returns the clock states the instruction at PC will take, which is what
+    // last_cycles will read after the next tick. Stepping is per instruction,
+    // never part way through one, so this is the finest step there is.
+    #[must_use]
+    pub fn cycles_until_next_instruction(&self) -> u8 {
+        crate::disassembler::get_opcode_cycles(self.read_opcode().0) as u8
+    }
+
+    // Decodes the instruction at PC, the one the next tick will run, into a
+    // listing line, operand included. Unlike last_opcode, this always reflects
+    // the current PC.
+    #[must_use]
+    pub fn next_instruction(&self) -> crate::disassembler::DisassembledLine {
+        crate::disassembler::line_at(self, self.pc)
+    }
+
+    // Returns the memory address the instruction at pc will touch: HL for the
+    // M forms, the D16 for LDA/STA/SHLD/LHLD, BC or DE for LDAX/STAX, and SP for
+    // anything that uses the stack. None if it doesn't touch memory.
+    #[must_use]
+    pub fn effective_address(&self) -> Option<u16> {
+        let (op, dl, dh) = self.read_opcode();
+        match op {
+            // MOV to/from M, the arithmetic/logical ops on M, INR/DCR/MVI M
+            0x70..=0x75 | 0x77 | 0x34..=0x36 => Some(self.get_register_pair(Registers::HL)),
+            0x40..=0xBF if op & 0x07 == 0x06 && op != 0x76 => {
+                Some(self.get_register_pair(Registers::HL))
+            }
+            0x22 | 0x2A | 0x32 | 0x3A => Some(u16::from(dh) << 8 | u16::from(dl)),
+            0x02 | 0x0A => Some(self.get_register_pair(Registers::BC)),
+            0x12 | 0x1A => Some(self.get_register_pair(Registers::DE)),
+            // PUSH/POP, CALL/RET (all forms), RST and XTHL
+            0xC9 | 0xD9 | 0xCD | 0xDD | 0xED | 0xFD | 0xE3 => Some(self.sp),
+            0xC0..=0xFF
+                if matches!(op & 0x0F, 0x01 | 0x05) || matches!(op & 0x07, 0x00 | 0x04 | 0x07) =>
+            {
+                Some(self.sp)
+            }
+            _ => None,
+        }
     }
 
     /// This processes the opcodes beginning with the pattern "0X"
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_0x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_0x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
-            0x00 => self.op_00(),                       // NOP
+            0x00 | 0x08 => self.op_00(),                // NOP (0x08 undocumented)
             0x01 => self.op_lxi(Registers::BC, dl, dh), // LXI B,D16
             0x02 => self.op_stax(Registers::BC),        // STAX (BC)
             0x03 => self.op_inx(Registers::BC),         // INX B
@@ -349,20 +1125,14 @@ impl Cpu {
             0x05 => self.op_dcr(Registers::B),          // DCR B
             0x06 => self.op_mvi(Registers::B, dl),      // MVI B, D8
             0x07 => self.op_rotl(false),                // RLC (Rotate left)
-            //0x08
-            0x09 => self.op_dad(Registers::B),     // DAD BC
-            0x0A => self.op_ldax(Registers::BC),   // LDAX BC
-            0x0B => self.op_dcx(Registers::BC),    // DCX BC
-            0x0C => self.op_inr(Registers::C),     // INR C
-            0x0D => self.op_dcr(Registers::C),     // DCR D
-            0x0E => self.op_mvi(Registers::C, dl), // MVI C, D8
-            0x0F => self.op_rotr(false),           // RRC
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0x09 => self.op_dad(Registers::B),          // DAD BC
+            0x0A => self.op_ldax(Registers::BC),        // LDAX BC
+            0x0B => self.op_dcx(Registers::BC),         // DCX BC
+            0x0C => self.op_inr(Registers::C),          // INR C
+            0x0D => self.op_dcr(Registers::C),          // DCR D
+            0x0E => self.op_mvi(Registers::C, dl),      // MVI C, D8
+            0x0F => self.op_rotr(false),                // RRC
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -372,12 +1142,12 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_1x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_1x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
-            //0x10
+            0x10 | 0x18 => self.op_00(),                // NOP (undocumented)
             0x11 => self.op_lxi(Registers::DE, dl, dh), // LXI D,D16
             0x12 => self.op_stax(Registers::DE),        // STAX (DE)
             0x13 => self.op_inx(Registers::DE),         // INX DE
@@ -392,12 +1162,7 @@ impl Cpu {
             0x1D => self.op_dcr(Registers::E),          // DCR E
             0x1E => self.op_mvi(Registers::E, dl),      // MVI E
             0x1F => self.op_rotr(true),                 // RAR
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -407,30 +1172,28 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_2x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_2x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
+            0x20 if self.cpu_mode == CpuMode::I8085 => self.op_rim(), // RIM (8085 only)
+            0x20 | 0x28 => self.op_00(), // NOP (0x20 is RIM in 8085 mode)
             0x21 => self.op_lxi(Registers::HL, dl, dh), // LXI X,D16
-            0x23 => self.op_inx(Registers::HL),         // INX HL
-            0x24 => self.op_inr(Registers::H),          // INR H
-            0x25 => self.op_dcr(Registers::H),          // DCR H
-            0x26 => self.op_mvi(Registers::H, dl),      // MVI H, D8
-            0x27 => self.op_daa(),                      // DAA
-            0x29 => self.op_dad(Registers::H),          // DAD HL
-            0x2A => self.lhld(dl, dh),                  // LDA DL DH
-            0x2B => self.op_dcx(Registers::HL),         // DCX HL
-            0x2C => self.op_inr(Registers::L),          // INR L
-            0x2D => self.op_dcr(Registers::L),          // DCR L
-            0x2E => self.op_mvi(Registers::L, dl),      // MVI L
-            0x2F => self.op_comp(Registers::A),         // CMA
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0x22 => self.shld(dl, dh),   // SHLD DL DH
+            0x23 => self.op_inx(Registers::HL), // INX HL
+            0x24 => self.op_inr(Registers::H), // INR H
+            0x25 => self.op_dcr(Registers::H), // DCR H
+            0x26 => self.op_mvi(Registers::H, dl), // MVI H, D8
+            0x27 => self.op_daa(),       // DAA
+            0x29 => self.op_dad(Registers::H), // DAD HL
+            0x2A => self.lhld(dl, dh),   // LHLD DL DH
+            0x2B => self.op_dcx(Registers::HL), // DCX HL
+            0x2C => self.op_inr(Registers::L), // INR L
+            0x2D => self.op_dcr(Registers::L), // DCR L
+            0x2E => self.op_mvi(Registers::L, dl), // MVI L
+            0x2F => self.op_comp(Registers::A), // CMA
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -440,31 +1203,28 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_3x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_3x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
+            0x30 if self.cpu_mode == CpuMode::I8085 => self.op_sim(), // SIM (8085 only)
+            0x30 | 0x38 => self.op_00(), // NOP (0x30 is SIM in 8085 mode)
             0x31 => self.op_lxi(Registers::SP, dl, dh), // LXI SP, D16
-            0x32 => self.op_sta(dl, dh),                // STA (adr)<-A
-            0x33 => self.op_inx(Registers::SP),         // INX SP
-            0x34 => self.op_inr(Registers::HL),         // INR (HL)
-            0x35 => self.op_dcr(Registers::HL),         // DCR (HL)
-            0x36 => self.op_mvi(Registers::HL, dl),     // MVI (HL)<-D8
-            0x37 => self.op_stc(),                      // STC
-            0x39 => self.op_dad(Registers::SP),         // DAD SP
-            0x3A => self.op_lda(dl, dh),                // LDA adr
-            0x3B => self.op_dcx(Registers::SP),         // DCX SP
-            0x3C => self.op_inr(Registers::A),          // INR A
-            0x3D => self.op_dcr(Registers::A),          // DCR A
-            0x3E => self.op_mvi(Registers::A, dl),      // MVI A
-            0x3F => self.op_cmc(),                      // CMC
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0x32 => self.op_sta(dl, dh), // STA (adr)<-A
+            0x33 => self.op_inx(Registers::SP), // INX SP
+            0x34 => self.op_inr(Registers::HL), // INR (HL)
+            0x35 => self.op_dcr(Registers::HL), // DCR (HL)
+            0x36 => self.op_mvi(Registers::HL, dl), // MVI (HL)<-D8
+            0x37 => self.op_stc(),       // STC
+            0x39 => self.op_dad(Registers::SP), // DAD SP
+            0x3A => self.op_lda(dl, dh), // LDA adr
+            0x3B => self.op_dcx(Registers::SP), // DCX SP
+            0x3C => self.op_inr(Registers::A), // INR A
+            0x3D => self.op_dcr(Registers::A), // DCR A
+            0x3E => self.op_mvi(Registers::A, dl), // MVI A
+            0x3F => self.op_cmc(),       // CMC
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -474,7 +1234,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_4x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_4x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0x40 => self.op_mov(Registers::B, Registers::B), // MOV B <- B
             0x41 => self.op_mov(Registers::B, Registers::C), // MOV B <- C
@@ -492,12 +1252,7 @@ impl Cpu {
             0x4D => self.op_mov(Registers::C, Registers::L), // MOV C <- L
             0x4E => self.op_mov(Registers::C, Registers::HL), // MOV C <- HL
             0x4F => self.op_mov(Registers::C, Registers::A), // MOV C <- A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -507,7 +1262,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_5x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_5x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0x50 => self.op_mov(Registers::D, Registers::B), // MOV D <- B
             0x51 => self.op_mov(Registers::D, Registers::C), // MOV D <- C
@@ -525,12 +1280,7 @@ impl Cpu {
             0x5D => self.op_mov(Registers::E, Registers::L), // MOV E <- L
             0x5E => self.op_mov(Registers::E, Registers::HL), // MOV E <- HL
             0x5F => self.op_mov(Registers::E, Registers::A), // MOV E <- A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -540,7 +1290,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_6x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_6x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0x60 => self.op_mov(Registers::H, Registers::B), // MOV H <- B
             0x61 => self.op_mov(Registers::H, Registers::C), // MOV H <- C
@@ -558,12 +1308,7 @@ impl Cpu {
             0x6D => self.op_mov(Registers::L, Registers::L), // MOV L <- L
             0x6E => self.op_mov(Registers::L, Registers::HL), // MOV L <- HL
             0x6F => self.op_mov(Registers::L, Registers::A), // MOV L <- A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -573,7 +1318,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_7x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_7x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0x70 => self.op_mov(Registers::HL, Registers::B), // MOV M,B	1		(HL) <- B
             0x71 => self.op_mov(Registers::HL, Registers::C), // MOV M,C	1		(HL) <- C
@@ -591,12 +1336,7 @@ impl Cpu {
             0x7D => self.op_mov(Registers::A, Registers::L),  // MOV A,L
             0x7E => self.op_mov(Registers::A, Registers::HL), // MOV A,(HL)
             0x7F => self.op_mov(Registers::A, Registers::A),  // MOV A,A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -606,7 +1346,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_8x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_8x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0x80 => self.op_add(Registers::B),  // ADD B
             0x81 => self.op_add(Registers::C),  // ADD C
@@ -624,12 +1364,7 @@ impl Cpu {
             0x8D => self.op_adc(Registers::L),  // ADC L
             0x8E => self.op_adc(Registers::HL), // ADC M
             0x8F => self.op_adc(Registers::A),  // ADC A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -639,7 +1374,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_9x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_9x(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let sub = self.get_flag(super::FLAG_CARRY);
         let i = match opcode.0 {
             0x90 => self.op_sub(Registers::B, 0),    // SUB B
@@ -658,12 +1393,7 @@ impl Cpu {
             0x9D => self.op_sub(Registers::L, sub),  // SBB B
             0x9E => self.op_sub(Registers::HL, sub), // SBB B
             0x9F => self.op_sub(Registers::A, sub),  // SBB B
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -673,7 +1403,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_ax(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_ax(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0xA0 => self.op_ana(Registers::B),  // ANA B
             0xA1 => self.op_ana(Registers::C),  // ANA C
@@ -691,12 +1421,7 @@ impl Cpu {
             0xAD => self.op_xra(Registers::L),  // XRA L
             0xAE => self.op_xra(Registers::HL), // XRA HL
             0xAF => self.op_xra(Registers::A),  // XRA A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -706,7 +1431,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_bx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_bx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let i = match opcode.0 {
             0xB0 => self.op_ora(Registers::B),  // ORA B
             0xB1 => self.op_ora(Registers::C),  // ORA C
@@ -724,12 +1449,7 @@ impl Cpu {
             0xBD => self.op_cmp(Registers::L),  // CMP L
             0xBE => self.op_cmp(Registers::HL), // CMP (HL)
             0xBF => self.op_cmp(Registers::A),  // CMP A
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -739,7 +1459,7 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_cx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_cx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
         let flag_carry = self.test_flag(super::FLAG_CARRY);
@@ -752,7 +1472,7 @@ impl Cpu {
             0xC4 => self.op_call_if(super::FLAG_ZERO, false, dl, dh), // CNZ
             0xC5 => self.op_push(Registers::B),            // PUSH B
             0xC6 => self.op_adi_aci(dl, false),            // ADI
-            0xC7 => self.op_rst(0b000),                    // RST 0
+            0xC7 => self.op_rst(0b000)?,                   // RST 0
             0xC8 => self.op_rets(super::FLAG_CARRY, true), // RC
             0xC9 => self.op_ret(),                         // RET
             0xCA => self.op_jz(dl, dh),                    // JZ
@@ -760,13 +1480,8 @@ impl Cpu {
             0xCC => self.op_call_if(super::FLAG_ZERO, true, dl, dh), // CZ
             0xCD => self.op_call(dl, dh),                            // CALL Addr
             0xCE => self.op_adi_aci(dl, flag_carry),                 // ACI
-            0xCF => self.op_rst(0b001),                              // RST 1
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0xCF => self.op_rst(0b001)?,                             // RST 1
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -776,25 +1491,23 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_dx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_dx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
             0xD0 => self.op_rets(super::FLAG_CARRY, false), // RNC
             0xD1 => self.op_pop(Registers::D),              // POP D
+            0xD2 => self.op_jmp_if(super::FLAG_CARRY, false, dl, dh), // JNC
             0xD3 => self.op_out(dl),                        // OUT
             0xD4 => self.op_call_if(super::FLAG_CARRY, false, dl, dh), // CNC
             0xD5 => self.op_push(Registers::D),             // PUSH D
-            0xD7 => self.op_rst(0b010),                     // RST 2
+            0xD7 => self.op_rst(0b010)?,                    // RST 2
+            0xDA => self.op_jmp_if(super::FLAG_CARRY, true, dl, dh), // JC
+            0xDB => self.op_in(dl),                         // IN
             0xDC => self.op_call_if(super::FLAG_CARRY, true, dl, dh), // CC
-            0xDF => self.op_rst(0b011),                     // RST 3
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0xDF => self.op_rst(0b011)?,                    // RST 3
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -804,26 +1517,24 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_ex(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_ex(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
             0xE0 => self.op_rets(super::FLAG_PARITY, false), // RPO
             0xE1 => self.op_pop(Registers::H),               // POP H
+            0xE2 => self.op_jmp_if(super::FLAG_PARITY, false, dl, dh), // JPO
             0xE4 => self.op_call_if(super::FLAG_PARITY, false, dl, dh), // CPO
             0xE5 => self.op_push(Registers::H),              // PUSH H
-            0xE7 => self.op_rst(0b100),                      // RST 4
+            0xE7 => self.op_rst(0b100)?,                     // RST 4
             0xE8 => self.op_rets(super::FLAG_PARITY, true),  // RPE
+            0xE9 => self.op_pchl(),                          // PCHL
+            0xEA => self.op_jmp_if(super::FLAG_PARITY, true, dl, dh), // JPE
             0xEB => self.op_xchg(),                          // XCHG
             0xEC => self.op_call_if(super::FLAG_PARITY, true, dl, dh), // CPE
-            0xEF => self.op_rst(0b101),                      // RST 5
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0xEF => self.op_rst(0b101)?,                     // RST 5
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -833,26 +1544,23 @@ impl Cpu {
     ///
     /// # Errors
     /// Will return ERROR if opcode was not recognized
-    pub fn opcodes_fx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, String> {
+    pub fn opcodes_fx(&mut self, opcode: (u8, u8, u8)) -> Result<ProgramCounter, CpuError> {
         let dl = opcode.1; // Potential data points for usage by an instruction
         let dh = opcode.2; // Potential data points for usage by an instruction
 
         let i = match opcode.0 {
             0xF0 => self.op_rets(super::FLAG_SIGN, false), // RP
             0xF1 => self.op_pop(Registers::SW),            // POP SW
+            0xF2 => self.op_jmp_if(super::FLAG_SIGN, false, dl, dh), // JP
             0xF4 => self.op_call_if(super::FLAG_SIGN, false, dl, dh), // CP
             0xF5 => self.op_push(Registers::SW),           // Push SW
             0xFE => self.op_fe(dl),                        // CPI
-            0xF7 => self.op_rst(0b110),                    // RST 6
+            0xF7 => self.op_rst(0b110)?,                   // RST 6
             0xF8 => self.op_rets(super::FLAG_SIGN, true),  // RM
+            0xFA => self.op_jmp_if(super::FLAG_SIGN, true, dl, dh), // JM
             0xFC => self.op_call_if(super::FLAG_SIGN, true, dl, dh), // CM
-            0xFF => self.op_rst(0b111),                    // RST 7
-            _ => {
-                return Err(format!(
-                    "!! OPCODE: {:#04X} {:#010b} is unknown !!",
-                    opcode.0, opcode.0
-                ))
-            }
+            0xFF => self.op_rst(0b111)?,                   // RST 7
+            _ => return Err(CpuError::UnknownOpcode(opcode.0)),
         };
 
         Ok(i)
@@ -865,8 +1573,13 @@ impl Cpu {
     ///  Data (1 or 2 bytes) depending on opcode.  Little endian.
     ///
     /// # Errors
-    /// It will return ERROR if the opcode was not recognized
-    pub fn run_opcode(&mut self, opcode: (u8, u8, u8)) -> Result<(), String> {
+    /// It will return ERROR if the opcode was not recognized, unless the
+    /// unknown opcode policy is ``NopAndLog``
+    pub fn run_opcode(&mut self, opcode: (u8, u8, u8)) -> Result<(), CpuError> {
+        let start = self.pc;
+        self.last_branch_taken = false;
+        self.last_compare = None;
+
         // D8 = 8 bits (1st byte = y)
         // D16 = 16 bits (1st (y) and 2nd byte (x))
         let i = match opcode.0 {
@@ -889,28 +1602,60 @@ impl Cpu {
         };
 
         match i {
-            Ok(ProgramCounter::Next) => self.pc += super::OPCODE_SIZE,
-            Ok(ProgramCounter::Two) => self.pc += super::OPCODE_SIZE * 2,
-            Ok(ProgramCounter::Three) => self.pc += super::OPCODE_SIZE * 3,
-            Ok(ProgramCounter::Jump(d)) => self.pc = d,
+            Ok(p) => {
+                self.pc = p.advance(self.pc);
+                let size = crate::disassembler::get_opcode_size(opcode.0);
+                self.last_branch_taken = self.pc != (start + size) & 0xFFFF;
+            }
+            Err(CpuError::UnknownOpcode(op)) if self.unknown_policy == UnknownPolicy::NopAndLog => {
+                // Logged once per site, so a ROM spinning through data doesn't grow
+                // the log every time around
+                let site = (self.pc as u16, op);
+                if !self.unknown_opcodes.contains(&site) {
+                    self.unknown_opcodes.push(site);
+                }
+                self.pc = ProgramCounter::Next.advance(self.pc);
+            }
             Err(e) => return Err(e),
         }
 
         Ok(())
     }
 
+    // RIM (8085 only)
+    // Reads the interrupt masks into the low three bits of A. Nothing here
+    // raises RST 5.5/6.5/7.5 or drives SID, so the other bits read as zero.
+    pub fn op_rim(&mut self) -> ProgramCounter {
+        self.a = self.interrupt_mask & 0x07;
+        ProgramCounter::Next
+    }
+
+    // SIM (8085 only)
+    // When bit 3 of A (mask set enable) is set, the low three bits of A become
+    // the new interrupt masks. Serial output is not modelled.
+    pub fn op_sim(&mut self) -> ProgramCounter {
+        if self.a & 0x08 != 0 {
+            self.interrupt_mask = self.a & 0x07;
+        }
+        ProgramCounter::Next
+    }
+
     // LHLD
+    // L is loaded from addr and H from addr + 1, which wraps to 0x0000
     pub fn lhld(&mut self, dl: u8, dh: u8) -> ProgramCounter {
-        let mut addr: u16 = u16::from(dh) << 8 | u16::from(dl);
-        self.l = match self.memory.get(addr as usize) {
-            Some(&v) => v,
-            None => 0,
-        };
-        addr = addr.overflowing_add(0x01).0;
-        self.h = match self.memory.get(addr as usize) {
-            Some(&v) => v,
-            None => 0,
-        };
+        let addr: u16 = u16::from(dh) << 8 | u16::from(dl);
+        self.l = self.memory[addr as usize];
+        self.h = self.memory[addr.wrapping_add(1) as usize];
+
+        ProgramCounter::Three
+    }
+
+    // SHLD
+    // L is stored at addr and H at addr + 1, which wraps to 0x0000
+    pub fn shld(&mut self, dl: u8, dh: u8) -> ProgramCounter {
+        let addr: u16 = u16::from(dh) << 8 | u16::from(dl);
+        self.memory[addr as usize] = self.l;
+        self.memory[addr.wrapping_add(1) as usize] = self.h;
 
         ProgramCounter::Three
     }
@@ -924,6 +1669,14 @@ impl Cpu {
         ProgramCounter::Two
     }
 
+    // IN D8
+    // Would read the device on the port in the data portion of this command;
+    // with none attached, A gets the configured default instead
+    pub fn op_in(&mut self, _data: u8) -> ProgramCounter {
+        self.a = self.in_default;
+        ProgramCounter::Two
+    }
+
     #[must_use]
     #[allow(clippy::unused_self)]
     pub fn op_00(&self) -> ProgramCounter {
@@ -964,36 +1717,42 @@ impl Cpu {
                 let (res, of) = self.b.overflowing_add(1);
                 let ac = self.will_ac(1, self.b);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.b, 1, res, false);
                 self.b = res;
             }
             Registers::C => {
                 let (res, of) = self.c.overflowing_add(1);
                 let ac = self.will_ac(1, self.c);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.c, 1, res, false);
                 self.c = res;
             }
             Registers::D => {
                 let (res, of) = self.d.overflowing_add(1);
                 let ac = self.will_ac(1, self.d);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.d, 1, res, false);
                 self.d = res;
             }
             Registers::E => {
                 let (res, of) = self.e.overflowing_add(1);
-                let ac = self.will_ac(1, self.d);
+                let ac = self.will_ac(1, self.e);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.e, 1, res, false);
                 self.e = res;
             }
             Registers::H => {
                 let (res, of) = self.h.overflowing_add(1);
                 let ac = self.will_ac(1, self.h);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.h, 1, res, false);
                 self.h = res;
             }
             Registers::L => {
                 let (res, of) = self.l.overflowing_add(1);
                 let ac = self.will_ac(1, self.l);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.l, 1, res, false);
                 self.l = res;
             }
             Registers::HL => {
@@ -1001,12 +1760,14 @@ impl Cpu {
                 let ac = self.will_ac(1, val);
                 let (res, of) = val.overflowing_add(1);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(val, 1, res, false);
                 self.memory[self.get_addr_pointer()] = res;
             }
             Registers::A => {
                 let (res, of) = self.a.overflowing_add(1);
                 let ac = self.will_ac(1, self.a);
                 self.update_flags(res, Some(of), Some(ac));
+                self.update_8085_flags(self.a, 1, res, false);
                 self.a = res;
             }
             _ => (),
@@ -1021,25 +1782,30 @@ impl Cpu {
     //
     // Flags affected: Z, S, P, CY, AC
     pub fn op_sub(&mut self, reg: Registers, sub: u8) -> ProgramCounter {
-        let o: (u8, bool) = match reg {
-            Registers::A => self.a.overflowing_sub(self.a.overflowing_add(sub).0),
-            Registers::B => self.a.overflowing_sub(self.b.overflowing_add(sub).0),
-            Registers::C => self.a.overflowing_sub(self.c.overflowing_add(sub).0),
-            Registers::D => self.a.overflowing_sub(self.d.overflowing_add(sub).0),
-            Registers::E => self.a.overflowing_sub(self.e.overflowing_add(sub).0),
-            Registers::H => self.a.overflowing_sub(self.h.overflowing_add(sub).0),
-            Registers::L => self.a.overflowing_sub(self.l.overflowing_add(sub).0),
-            Registers::HL => self
-                .a
-                .overflowing_sub(self.memory[self.get_addr_pointer()].overflowing_add(sub).0),
-            _ => (0_u8, false),
+        let src: u8 = match reg {
+            Registers::A => self.a,
+            Registers::B => self.b,
+            Registers::C => self.c,
+            Registers::D => self.d,
+            Registers::E => self.e,
+            Registers::H => self.h,
+            Registers::L => self.l,
+            Registers::HL => self.memory[self.get_addr_pointer()],
+            _ => return ProgramCounter::Next,
         };
 
-        let ac = self.will_ac(o.0.wrapping_neg(), self.a.wrapping_neg()); // Because it's a subtraction
+        // Taken wide, so a carry in on top of 0xFF still counts as a borrow
+        let total = u16::from(src) + u16::from(sub);
+        let res = (u16::from(self.a).wrapping_sub(total) & 0xFF) as u8;
+        let borrow = u16::from(self.a) < total;
 
-        //self.update_flags(o.0, o.1, (1 & 0x0F) > (self.a & 0x0F));
-        self.update_flags(o.0, Some(o.1), Some(ac));
-        self.a = o.0;
+        // The 8080 subtracts by adding the complement, so AC is the carry out of
+        // bit 3 of a + !src + (1 - carry in)
+        let ac = (self.a & 0x0F) + (!src & 0x0F) + (1 - sub.min(1)) > 0x0F;
+
+        self.update_flags(res, Some(borrow), Some(ac));
+        self.update_8085_flags(self.a, src, res, true);
+        self.a = res;
         ProgramCounter::Next
     }
 
@@ -1053,42 +1819,50 @@ impl Cpu {
             Registers::A => {
                 let (res, of) = self.b.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.a & 0x0F)));
+                self.update_8085_flags(self.a, 1, res, true);
                 self.a = res;
             }
             Registers::B => {
                 let (res, of) = self.b.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.b & 0x0F)));
+                self.update_8085_flags(self.b, 1, res, true);
                 self.b = res;
             }
             Registers::C => {
                 let (res, of) = self.c.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.c & 0x0F)));
+                self.update_8085_flags(self.c, 1, res, true);
                 self.c = res;
             }
             Registers::D => {
                 let (res, of) = self.d.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.d & 0x0F)));
+                self.update_8085_flags(self.d, 1, res, true);
                 self.d = res;
             }
             Registers::E => {
                 let (res, of) = self.e.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.e & 0x0F)));
+                self.update_8085_flags(self.e, 1, res, true);
                 self.e = res;
             }
             Registers::H => {
                 let (res, of) = self.h.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.h & 0x0F)));
+                self.update_8085_flags(self.h, 1, res, true);
                 self.h = res;
             }
             Registers::L => {
                 let (res, of) = self.l.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (self.l & 0x0F)));
+                self.update_8085_flags(self.l, 1, res, true);
                 self.l = res;
             }
             Registers::HL => {
                 let mem = self.memory[self.get_addr_pointer()];
                 let (res, of) = mem.overflowing_sub(1);
                 self.update_flags(res, Some(of), Some((1 & 0x0F) > (mem & 0x0F)));
+                self.update_8085_flags(mem, 1, res, true);
                 self.memory[self.get_addr_pointer()] = res;
             }
 
@@ -1117,27 +1891,27 @@ impl Cpu {
         match reg {
             Registers::B => {
                 // BC Pair 0xC5
-                self.memory[usize::from(self.sp - 2)] = self.c;
-                self.memory[usize::from(self.sp - 1)] = self.b;
+                self.memory[usize::from(self.sp.wrapping_sub(2))] = self.c;
+                self.memory[usize::from(self.sp.wrapping_sub(1))] = self.b;
             }
             Registers::D => {
                 // DE Pair 0xD5
-                self.memory[usize::from(self.sp - 2)] = self.e;
-                self.memory[usize::from(self.sp - 1)] = self.d;
+                self.memory[usize::from(self.sp.wrapping_sub(2))] = self.e;
+                self.memory[usize::from(self.sp.wrapping_sub(1))] = self.d;
             }
             Registers::H => {
                 // HL Pair 0xE5
-                self.memory[usize::from(self.sp - 2)] = self.l;
-                self.memory[usize::from(self.sp - 1)] = self.h;
+                self.memory[usize::from(self.sp.wrapping_sub(2))] = self.l;
+                self.memory[usize::from(self.sp.wrapping_sub(1))] = self.h;
             }
             Registers::SW => {
                 // SW 0xF5
-                self.memory[usize::from(self.sp - 2)] = self.flags;
-                self.memory[usize::from(self.sp - 1)] = self.a;
+                self.memory[usize::from(self.sp.wrapping_sub(2))] = self.flags;
+                self.memory[usize::from(self.sp.wrapping_sub(1))] = self.a;
             }
             _ => (),
         };
-        self.sp -= 2;
+        self.sp = self.sp.wrapping_sub(2);
         ProgramCounter::Next
     }
 
@@ -1148,26 +1922,26 @@ impl Cpu {
             Registers::B => {
                 // BC Pair 0xC1
                 self.c = self.memory[usize::from(self.sp)];
-                self.b = self.memory[usize::from(self.sp + 1)];
+                self.b = self.memory[usize::from(self.sp.wrapping_add(1))];
             }
             Registers::D => {
                 // DE Pair 0xD1
                 self.e = self.memory[usize::from(self.sp)];
-                self.d = self.memory[usize::from(self.sp + 1)];
+                self.d = self.memory[usize::from(self.sp.wrapping_add(1))];
             }
             Registers::H => {
                 // HL Pair 0xE1
                 self.l = self.memory[usize::from(self.sp)];
-                self.h = self.memory[usize::from(self.sp + 1)];
+                self.h = self.memory[usize::from(self.sp.wrapping_add(1))];
             }
             Registers::SW => {
                 // SW 0xF1
                 self.flags = self.memory[usize::from(self.sp)];
-                self.a = self.memory[usize::from(self.sp + 1)];
+                self.a = self.memory[usize::from(self.sp.wrapping_add(1))];
             }
             _ => (),
         };
-        self.sp += 2;
+        self.sp = self.sp.wrapping_add(2);
 
         ProgramCounter::Next
     }
@@ -1197,14 +1971,14 @@ impl Cpu {
     // Performs the Double Add (DAD) functionality
     // Sets H to the value according to the supplied register
     // Basically: HL = HL+<Selected register pair>
+    // Only the carry flag is affected, set on a 16 bit overflow and reset otherwise
     pub fn op_dad(&mut self, source: Registers) -> ProgramCounter {
-        //let val = usize::from(u16::from(self.h) << 8 | u16::from(self.l));
-        let val = usize::from(self.get_register_pair(Registers::HL));
+        let val = self.get_register_pair(Registers::HL);
 
-        let src: usize = match source {
-            Registers::B | Registers::BC => usize::from(self.get_register_pair(Registers::BC)),
-            Registers::D | Registers::DE => usize::from(self.get_register_pair(Registers::DE)),
-            Registers::SP => usize::from(self.get_register_pair(Registers::SP)),
+        let src: u16 = match source {
+            Registers::B | Registers::BC => self.get_register_pair(Registers::BC),
+            Registers::D | Registers::DE => self.get_register_pair(Registers::DE),
+            Registers::SP => self.get_register_pair(Registers::SP),
             Registers::H | Registers::HL => val,
             _ => 0,
         };
@@ -1216,6 +1990,8 @@ impl Cpu {
 
         if of {
             self.set_flag(super::FLAG_CARRY);
+        } else {
+            self.reset_flag(super::FLAG_CARRY);
         }
 
         ProgramCounter::Next
@@ -1250,6 +2026,7 @@ impl Cpu {
                 let mut pair: u16 = self.get_register_pair(target);
                 pair = pair.overflowing_add(0x01).0;
                 self.set_register_pair(target, pair);
+                self.update_x5_16(pair == 0x0000);
             }
             _ => (),
         }
@@ -1281,6 +2058,7 @@ impl Cpu {
         let mut val = self.get_register_pair(reg);
         val = val.overflowing_sub(1).0;
         self.set_register_pair(reg, val);
+        self.update_x5_16(val == 0xFFFF);
 
         ProgramCounter::Next
     }
@@ -1324,17 +2102,58 @@ impl Cpu {
         ProgramCounter::Next
     }
 
-    // The contents of the program counter (16bit)
-    // are pushed onto the stack, providing a return address for
-    // later use by a RETURN instruction.
-    // Program execution continues at memory address:
-    // OOOOOOOOOOEXPOOOB
-    pub fn op_rst(&mut self, loc: u8) -> ProgramCounter {
-        self.memory[usize::from(self.sp - 2)] = (self.pc as u16 >> 8) as u8;
-        self.memory[usize::from(self.sp - 1)] = (self.pc as u16 & 0xFF) as u8;
-        self.sp -= 2;
+    /// The contents of the program counter (16bit)
+    /// are pushed onto the stack, providing a return address for
+    /// later use by a RETURN instruction.
+    /// Program execution continues at memory address:
+    /// ``OOOOOOOOOOEXPOOOB``
+    ///
+    /// Only vectors 0 through 7 exist; anything larger is refused before the
+    /// stack is touched.
+    ///
+    /// # Errors
+    /// Will return an error if ``loc`` is greater than 7
+    pub fn op_rst(&mut self, loc: u8) -> Result<ProgramCounter, CpuError> {
+        // The RST is one byte, so the return is to the instruction after it
+        self.enter_vector(loc, (self.pc as u16).wrapping_add(1))
+    }
 
-        ProgramCounter::Jump((loc << 3) as usize)
+    // Pushes ret and jumps to RST vector loc, refusing vectors above 7
+    fn enter_vector(&mut self, loc: u8, ret: u16) -> Result<ProgramCounter, CpuError> {
+        if loc > 0b111 {
+            return Err(CpuError::InvalidVector(loc));
+        }
+
+        self.push_return(ret);
+        Ok(ProgramCounter::Jump(usize::from(loc) << 3))
+    }
+
+    // Pushes a return address for RET to pop, high byte at SP-1 and low byte
+    // at SP-2. SP wraps rather than underflowing.
+    fn push_return(&mut self, ret: u16) {
+        self.memory[usize::from(self.sp.wrapping_sub(1))] = (ret >> 8) as u8;
+        self.memory[usize::from(self.sp.wrapping_sub(2))] = (ret & 0xFF) as u8;
+        self.sp = self.sp.wrapping_sub(2);
+    }
+
+    /// Services RST ``n`` straight away: the current PC is pushed and the CPU
+    /// jumps to the vector, waking it from HLT if it was halted.  This is the
+    /// reset-line style of interrupt and is never masked.
+    ///
+    /// # Errors
+    /// Will return an error if ``n`` is not a valid RST vector (0..=7)
+    pub fn force_interrupt(&mut self, n: u8) -> Result<(), CpuError> {
+        // Nothing was fetched, so the return is to PC itself
+        let ret = self.pc as u16;
+        if let ProgramCounter::Jump(dest) = self.enter_vector(n, ret)? {
+            self.pc = dest;
+        }
+        self.set_nop(false);
+        self.interrupts_serviced = self.interrupts_serviced.saturating_add(1);
+        self.last_branch_taken = true;
+        log::debug!("Serviced interrupt RST {}, returning to {:#06X}", n, ret);
+
+        Ok(())
     }
 
     // JZ (Jump if zero)
@@ -1367,22 +2186,34 @@ impl Cpu {
         ProgramCounter::Jump(dest.into())
     }
 
+    // Jumps if the flag's supplied value matches the supplied sign
+    pub fn op_jmp_if(&mut self, flag: u8, sign: bool, x: u8, y: u8) -> ProgramCounter {
+        if sign == self.test_flag(flag) {
+            return self.op_jmp(x, y);
+        }
+        ProgramCounter::Three
+    }
+
+    // PCHL (PC.hi <- H; PC.lo <- L)
+    #[must_use]
+    pub fn op_pchl(&self) -> ProgramCounter {
+        ProgramCounter::Jump(self.get_register_pair(Registers::HL).into())
+    }
+
     // RET (PC.lo <- (sp); PC.hi<-(sp+1); SP <- SP+2)
     pub fn op_ret(&mut self) -> ProgramCounter {
         let pc_lo = match self.memory.get(usize::from(self.sp)) {
             Some(&v) => v,
             None => 0,
         };
-        let pc_hi = match self.memory.get(usize::from(self.sp + 1)) {
+        let pc_hi = match self.memory.get(usize::from(self.sp.wrapping_add(1))) {
             Some(&v) => v,
             None => 0,
         };
         let dest: u16 = u16::from(pc_hi) << 8 | u16::from(pc_lo);
-        self.pc = dest as usize; // Set our PC back to where we were
-        self.sp += 2;
+        self.sp = self.sp.wrapping_add(2);
 
-        //ProgramCounter::Jump(dest.into())
-        ProgramCounter::Three // And go to the next op
+        ProgramCounter::Jump(dest.into()) // Back to the instruction after the CALL
     }
 
     // Returns if the flag supplied's value matches the supplied sign
@@ -1404,12 +2235,8 @@ impl Cpu {
 
     // (SP-1)<-PC.hi;(SP-2)<-PC.lo;SP<-SP-2;PC=adr
     pub fn op_call(&mut self, x: u8, y: u8) -> ProgramCounter {
-        // Save away the current PC hi/lo into the stack
-        let pc_hi = self.pc >> 8;
-        let pc_lo = self.pc & 0xFF;
-        self.memory[usize::from(self.sp - 1)] = pc_hi as u8;
-        self.memory[usize::from(self.sp - 2)] = pc_lo as u8;
-        self.sp -= 2;
+        // Save away the return address, just past this CALL, onto the stack
+        self.push_return((self.pc as u16).wrapping_add(3));
 
         // Tell the program counter where we want to go next
         let ys: u16 = u16::from(y) << 8;
@@ -1420,11 +2247,13 @@ impl Cpu {
 
     // CPI - Compare D16 to Accum, set flags accordingly
     pub fn op_fe(&mut self, data: u8) -> ProgramCounter {
+        self.last_compare = Some((self.a, data));
         // Subtract the data from register A and set flags on the result
         let (res, overflow) = self.a.overflowing_sub(data);
         let aux_carry = (self.a & 0x0F).wrapping_sub(data & 0x0F) > 0x0F;
 
         self.update_flags(res, Some(overflow), Some(aux_carry));
+        self.update_8085_flags(self.a, data, res, true);
 
         ProgramCounter::Two
     }
@@ -1501,6 +2330,7 @@ impl Cpu {
 
         let (res, of) = self.a.overflowing_add(to_add);
         let ac = self.will_ac(to_add, self.a);
+        self.update_8085_flags(self.a, to_add, res, false);
         self.a = res;
         self.update_flags(res, Some(of), Some(ac));
 
@@ -1511,27 +2341,36 @@ impl Cpu {
     // along with the CARRY flag's value
     // as well as update flags
     pub fn op_adc(&mut self, register: Registers) -> ProgramCounter {
-        let to_add: u8 = u8::from(self.test_flag(super::FLAG_CARRY))
-            + match register {
-                Registers::B => self.b,
-                Registers::C => self.c,
-                Registers::D => self.d,
-                Registers::E => self.e,
-                Registers::H => self.h,
-                Registers::L => self.l,
-                Registers::HL => self.memory[self.get_addr_pointer()],
-                Registers::A => self.a,
-                _ => 0_u8,
-            };
+        let to_add: u8 = match register {
+            Registers::B => self.b,
+            Registers::C => self.c,
+            Registers::D => self.d,
+            Registers::E => self.e,
+            Registers::H => self.h,
+            Registers::L => self.l,
+            Registers::HL => self.memory[self.get_addr_pointer()],
+            Registers::A => self.a,
+            _ => 0_u8,
+        };
+        let carry = self.get_flag(super::FLAG_CARRY);
 
-        let (res, of) = self.a.overflowing_add(to_add);
-        let ac = self.will_ac(to_add, self.a);
-        self.a = res;
-        self.update_flags(res, Some(of), Some(ac));
+        self.add_with_carry(to_add, carry);
 
         ProgramCounter::Next
     }
 
+    // Adds data and a carry in of 0 or 1 to A, setting the flags. Taken wide,
+    // so a carry in on top of 0xFF still carries out.
+    fn add_with_carry(&mut self, data: u8, carry: u8) {
+        let total = u16::from(self.a) + u16::from(data) + u16::from(carry);
+        let res = (total & 0xFF) as u8;
+        let ac = (self.a & 0x0F) + (data & 0x0F) + carry > 0x0F;
+
+        self.update_8085_flags(self.a, data, res, false);
+        self.a = res;
+        self.update_flags(res, Some(total > 0xFF), Some(ac));
+    }
+
     /// Add to the accumulator the supplied data byte after
     /// the opcode byte.
     ///
@@ -1541,16 +2380,7 @@ impl Cpu {
     ///
     /// Condition bits affected: Carry, Sign, Zero, Parity, Aux Carry
     pub fn op_adi_aci(&mut self, dl: u8, carry_bit: bool) -> ProgramCounter {
-        let mut to_add = dl;
-
-        if carry_bit {
-            to_add = to_add.overflowing_add(1).0; // Do we need to care about overflow here?
-        };
-
-        let ac = self.will_ac(to_add, self.a);
-        let (res, of) = self.a.overflowing_add(to_add);
-        self.a = res;
-        self.update_flags(res, Some(of), Some(ac));
+        self.add_with_carry(dl, u8::from(carry_bit));
 
         ProgramCounter::Two
     }
@@ -1615,9 +2445,10 @@ impl Cpu {
 
     /// The specified byte is logically ``ANDed`` bit
     /// by bit with the contents of the accumulator. The Carry bit
-    /// is reset to zero.
+    /// is reset to zero.  On the 8080 the Aux Carry bit is set to the
+    /// OR of bit 3 of both operands.
     pub fn op_ana(&mut self, register: Registers) -> ProgramCounter {
-        self.a &= match register {
+        let source_value = match register {
             Registers::B => self.b,
             Registers::C => self.c,
             Registers::D => self.d,
@@ -1628,16 +2459,17 @@ impl Cpu {
             Registers::A => self.a,
             _ => 0_u8,
         };
+        let ac = (self.a | source_value) & 0x08 == 0x08;
+        self.a &= source_value;
 
         self.reset_flag(super::FLAG_CARRY);
-        self.update_flags(self.a, None, None);
+        self.update_flags(self.a, None, Some(ac));
         ProgramCounter::Next
     }
 
     /// The specified byte is locally ``XORed`` bit by bit with the contents
-    /// of the accumulator.  The carry bit is reset to zero.
+    /// of the accumulator.  The carry and aux carry bits are reset to zero.
     pub fn op_xra(&mut self, register: Registers) -> ProgramCounter {
-        let orig_value = self.a;
         let source_value = match register {
             Registers::B => self.b,
             Registers::C => self.c,
@@ -1649,16 +2481,15 @@ impl Cpu {
             Registers::A => self.a,
             _ => 0_u8,
         };
-        let ac = self.will_ac(orig_value, source_value);
         self.a ^= source_value;
 
         self.reset_flag(super::FLAG_CARRY);
-        self.update_flags(self.a, None, Some(ac));
+        self.update_flags(self.a, None, Some(false));
         ProgramCounter::Next
     }
 
     /// The specified byte is localled ``ORed`` bit by bit with the contents
-    /// of the accumulator.  The carry bit is reset to zero.
+    /// of the accumulator.  The carry and aux carry bits are reset to zero.
     pub fn op_ora(&mut self, register: Registers) -> ProgramCounter {
         self.a |= match register {
             Registers::B => self.b,
@@ -1673,7 +2504,7 @@ impl Cpu {
         };
 
         self.reset_flag(super::FLAG_CARRY);
-        self.update_flags(self.a, None, None);
+        self.update_flags(self.a, None, Some(false));
 
         ProgramCounter::Next
     }
@@ -1701,6 +2532,8 @@ impl Cpu {
         let res = min.overflowing_sub(sub).0;
         let ac = self.will_ac(min.wrapping_neg(), sub.wrapping_neg()); // Because it's a subtraction
         self.update_flags(res, Some(sub > min), Some(ac));
+        self.update_8085_flags(min, sub, res, true);
+        self.last_compare = Some((min, sub));
 
         ProgramCounter::Next
     }
@@ -2,10 +2,12 @@ pub use crate::cpu::*;
 pub use crate::utils::*;
 
 use std::fmt;
+use std::io::Write;
 
 pub const HEADER: &str =
     "CYCLE :PC       Ins  S  l,   h,   sp      SZ0A0P1C  data(l,h)  B    Halt? : Command";
 
+#[must_use]
 pub fn disassemble(cpu: &Cpu, last_pc: usize) -> String {
     let i = get_opcode_text(cpu.last_opcode);
     let dl = cpu.last_opcode.1;
@@ -14,6 +16,62 @@ pub fn disassemble(cpu: &Cpu, last_pc: usize) -> String {
         cpu.cycle_count, last_pc, cpu.last_opcode.0, cpu.l, cpu.h, cpu.sp, cpu.flags, dl, dh, cpu.b, cpu.nop, i.code)
 }
 
+// Spells out the flags in SZ0A0P1C order, giving the letter for each flag that
+// is set and a '-' for each that is clear. The fixed bits are left as-is.
+#[must_use]
+pub fn flags_text(flags: u8) -> String {
+    "SZ0A0P1C"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if !c.is_alphabetic() || flags & (0b1000_0000 >> i) != 0 {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+// A compact, plain text view of the CPU: PC/SP and cycles, the registers, the
+// flags, and the last and next instructions.
+#[must_use]
+pub fn status_panel(cpu: &Cpu) -> String {
+    format!(
+        "PC:{:#06X} SP:{:#06X} CYCLES:{} INTS:{}\nA:{:#04X} B:{:#04X} C:{:#04X} D:{:#04X} E:{:#04X} H:{:#04X} L:{:#04X}\nFLAGS:{}\nLAST:{}\nNEXT:{}",
+        cpu.pc,
+        cpu.sp,
+        cpu.cycle_count,
+        cpu.interrupts_serviced(),
+        cpu.a,
+        cpu.b,
+        cpu.c,
+        cpu.d,
+        cpu.e,
+        cpu.h,
+        cpu.l,
+        flags_text(cpu.flags),
+        get_opcode_text(cpu.last_opcode),
+        get_opcode_text(cpu.read_opcode())
+    )
+}
+
+/// Writes a line of disassembly to ``out``, with the header every 25 cycles,
+/// but only when the CPU has disassembly turned on.  Nothing is written
+/// otherwise, so the CPU can be embedded without it talking on stdout.
+///
+/// # Errors
+/// Will return any error from writing to ``out``
+pub fn write_trace(cpu: &Cpu, line: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    if !cpu.disassemble {
+        return Ok(());
+    }
+    if cpu.cycle_count == 1 || (cpu.cycle_count % 25 == 0) {
+        writeln!(out, "{}", HEADER)?;
+    }
+    writeln!(out, "{}", line)
+}
+
 pub struct Instr {
     code: String, // The string defining what this this instr is actually doing
 }
@@ -24,72 +82,360 @@ impl fmt::Display for Instr {
     }
 }
 
+#[must_use]
 pub fn cmd(s: &str) -> Instr {
     Instr {
         code: s.to_string(),
     }
 }
 
+/// A single line of a listing, either a decoded instruction or a byte of data
+/// which was never reached while following the program.
+pub struct DisassembledLine {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub is_code: bool,
+}
+
+impl fmt::Display for DisassembledLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:#06X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+// Fills in the operand held in an instruction's bytes for the "D8", "D16" or
+// "Addr" placeholder in its mnemonic. Mnemonics without one are left as-is.
+#[must_use]
+pub fn with_operand(text: &str, bytes: &[u8]) -> String {
+    match bytes {
+        [_, dl] => text.replace("D8", &format!("{:#04X}", dl)),
+        [_, dl, dh] => {
+            let operand = format!("{:#06X}", make_pointer(*dl, *dh));
+            text.replace("D16", &operand).replace("Addr", &operand)
+        }
+        _ => text.to_string(),
+    }
+}
+
+// Disassembles the single instruction at addr, mnemonic with operand
+#[must_use]
+pub fn line_at(cpu: &Cpu, addr: usize) -> DisassembledLine {
+    let (op, bytes) = cpu.decode_at(addr);
+    DisassembledLine {
+        addr,
+        text: with_operand(&get_opcode_text(op).to_string(), &bytes),
+        bytes,
+        is_code: true,
+    }
+}
+
+// Disassembles start..end in a straight line, one instruction after the next,
+// giving one line per instruction of address, bytes and mnemonic with operand
+#[must_use]
+pub fn listing(cpu: &Cpu, start: usize, end: usize) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    while addr < end {
+        let line = line_at(cpu, addr);
+        out.push_str(&line.to_string());
+        out.push('\n');
+        addr += line.bytes.len();
+    }
+    out
+}
+
+/// Writes the ``listing`` of start..end out to ``file``
+///
+/// # Errors
+/// Will return an error if the file can't be written
+pub fn write_listing(cpu: &Cpu, start: usize, end: usize, file: &str) -> Result<(), String> {
+    std::fs::write(file, listing(cpu, start, end))
+        .map_err(|e| format!("Unable to write listing {}: {}", file, e))
+}
+
+// Returns the size in bytes of the instruction (opcode plus any data)
+#[must_use]
+pub fn get_opcode_size(op: u8) -> usize {
+    match op {
+        // MVI, the immediate arithmetic/logical ops, OUT and IN
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E | 0xC6 | 0xCE | 0xD6 | 0xDE
+        | 0xE6 | 0xEE | 0xF6 | 0xFE | 0xD3 | 0xDB => 2,
+        // LXI, SHLD, LHLD, STA, LDA and the jumps/calls (including undocumented aliases)
+        0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2A | 0x32 | 0x3A | 0xC2 | 0xC3 | 0xCA | 0xCB
+        | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC | 0xDD
+        | 0xE4 | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => 3,
+        _ => 1,
+    }
+}
+
+// Clock states taken by each opcode, from the 8080 programmers manual. The
+// conditional returns and calls are listed with their not-taken cost; taking
+// the branch costs 6 more.
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4, // 0x
+     4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4, // 1x
+     4, 10, 16,  5,  5,  5,  7,  4,  4, 10, 16,  5,  5,  5,  7,  4, // 2x
+     4, 10, 13,  5, 10, 10, 10,  4,  4, 10, 13,  5,  5,  5,  7,  4, // 3x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5, // 4x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5, // 5x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5, // 6x
+     7,  7,  7,  7,  7,  7,  7,  7,  5,  5,  5,  5,  5,  5,  7,  5, // 7x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 8x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 9x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // Ax
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // Bx
+     5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11, // Cx
+     5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11, // Dx
+     5, 10, 10, 18, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11, // Ex
+     5, 10, 10,  4, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11, // Fx
+];
+
+// Returns the number of clock states the instruction takes
+#[must_use]
+pub fn get_opcode_cycles(op: u8) -> usize {
+    usize::from(CYCLES[usize::from(op)])
+}
+
+// Broad groups of instructions, as laid out in the 8080 programmers manual, for
+// tools which want to reason about what an opcode does rather than which it is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpKind {
+    DataTransfer,
+    Arithmetic,
+    Logical,
+    Branch,
+    Stack,
+    IO,
+    Control,
+}
+
+// Returns the group the instruction belongs to
+#[must_use]
+pub fn get_opcode_kind(op: u8) -> OpKind {
+    match op {
+        // NOP (and its undocumented copies), HLT, DI, EI
+        0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0x76 | 0xF3 | 0xFB => {
+            OpKind::Control
+        }
+        0xD3 | 0xDB => OpKind::IO,
+        // PUSH, POP, XTHL, SPHL
+        0xC1 | 0xC5 | 0xD1 | 0xD5 | 0xE1 | 0xE3 | 0xE5 | 0xF1 | 0xF5 | 0xF9 => OpKind::Stack,
+        // Rotates, CMA, CMC, STC, ANA/XRA/ORA/CMP and their immediates
+        0x07
+        | 0x0F
+        | 0x17
+        | 0x1F
+        | 0x2F
+        | 0x37
+        | 0x3F
+        | 0xA0..=0xBF
+        | 0xE6
+        | 0xEE
+        | 0xF6
+        | 0xFE => OpKind::Logical,
+        // DAA, ADD/ADC/SUB/SBB and their immediates
+        0x27 | 0x80..=0x9F | 0xC6 | 0xCE | 0xD6 | 0xDE => OpKind::Arithmetic,
+        // MOV and XCHG
+        0x40..=0x7F | 0xEB => OpKind::DataTransfer,
+        // Everything else up top is a jump, call, return or RST
+        0xC0..=0xFF => OpKind::Branch,
+        // What remains below 0x40 is LXI, STAX, SHLD, STA, MVI, LDAX, LHLD, LDA
+        // or INX, INR, DCR, DAD, DCX
+        _ => match op & 0x0F {
+            0x01 | 0x02 | 0x06 | 0x0A | 0x0E => OpKind::DataTransfer,
+            _ => OpKind::Arithmetic,
+        },
+    }
+}
+
+// True for anything that can move PC somewhere other than the next
+// instruction: jumps, PCHL, calls, RSTs and returns
+#[must_use]
+pub fn is_branch(op: u8) -> bool {
+    matches!(
+        op,
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA | 0xE9
+    ) || is_call(op)
+        || is_return(op)
+}
+
+// True for CALL, the conditional calls and the RSTs, which all push a return
+// address
+#[must_use]
+pub fn is_call(op: u8) -> bool {
+    matches!(
+        op,
+        0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC
+    ) || is_rst(op)
+}
+
+// True for RET and the conditional returns
+#[must_use]
+pub fn is_return(op: u8) -> bool {
+    matches!(
+        op,
+        0xC9 | 0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8
+    )
+}
+
+// True for a branch which is always taken, regardless of the flags
+#[must_use]
+pub fn is_unconditional(op: u8) -> bool {
+    matches!(op, 0xC3 | 0xE9 | 0xCD | 0xC9) || is_rst(op)
+}
+
+fn is_rst(op: u8) -> bool {
+    op & 0b1100_0111 == 0b1100_0111
+}
+
+/// Disassembles memory by following the flow of the program from ``entry``
+/// rather than walking it linearly.  Jumps, calls and RSTs are followed;
+/// paths end at an unconditional JMP, RET, PCHL, HLT or when they reach an address
+/// that has already been decoded.  Any bytes inside the covered range that were
+/// never reached are returned as data lines.
+#[must_use]
+pub fn trace_disassemble(cpu: &Cpu, entry: usize) -> Vec<DisassembledLine> {
+    let len = cpu.memory.len();
+    let byte_at = |addr: usize| cpu.memory.get(addr).copied().unwrap_or(0);
+
+    let mut seen = vec![false; len];
+    let mut starts = vec![false; len];
+    let mut pending = vec![entry];
+
+    while let Some(mut addr) = pending.pop() {
+        while addr < len && !seen[addr] {
+            let op = cpu.memory[addr];
+            let size = get_opcode_size(op);
+            starts[addr] = true;
+            for s in seen.iter_mut().skip(addr).take(size) {
+                *s = true;
+            }
+
+            // Returns and PCHL (computed) can't be followed; an RST goes to its
+            // vector and everything else to its address operand
+            if is_branch(op) && !is_return(op) && op != 0xE9 {
+                if is_rst(op) {
+                    pending.push(usize::from(op & 0b0011_1000));
+                } else {
+                    pending.push(make_pointer(byte_at(addr + 1), byte_at(addr + 2)));
+                }
+            }
+
+            // HLT and unconditional jumps and returns end this run; conditional
+            // branches and calls continue on as well
+            if op == 0x76 || (is_unconditional(op) && !is_call(op)) {
+                break;
+            }
+            addr += size;
+        }
+    }
+
+    // Nothing decoded (entry out of range) leaves addr past last and an empty listing
+    let mut lines: Vec<DisassembledLine> = Vec::new();
+    let mut addr = starts.iter().position(|&s| s).unwrap_or(len);
+    let last = seen.iter().rposition(|&s| s).unwrap_or(0);
+    while addr <= last {
+        if starts[addr] {
+            let op = (byte_at(addr), byte_at(addr + 1), byte_at(addr + 2));
+            let size = get_opcode_size(op.0);
+            lines.push(DisassembledLine {
+                addr,
+                bytes: (addr..addr + size).map(byte_at).collect(),
+                text: with_operand(
+                    &get_opcode_text(op).to_string(),
+                    &(addr..addr + size).map(byte_at).collect::<Vec<u8>>(),
+                ),
+                is_code: true,
+            });
+            addr += size;
+        } else {
+            lines.push(DisassembledLine {
+                addr,
+                bytes: vec![byte_at(addr)],
+                text: format!("DB {:#04X}", byte_at(addr)),
+                is_code: false,
+            });
+            addr += 1;
+        }
+    }
+
+    lines
+}
+
 // Really this just prints stuff to the standard output so we can view details on what is
 // happening. Later, it will probably print out more of the registers, etc.
+#[must_use]
 #[allow(clippy::too_many_lines)]
 pub fn get_opcode_text(op: (u8, u8, u8)) -> Instr {
     match op.0 {
         0x00 => cmd("NOP"),
-        0x01 => cmd("LXI B"),
+        // Undocumented; 0x20 and 0x30 are RIM/SIM on the 8085 but NOPs on the 8080
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => cmd("*NOP"),
+        0x01 => cmd("LXI B,D16"),
         0x02 => cmd("STAX (BC)"),
         0x03 => cmd("INX BC"),
         0x04 => cmd("INR B"),
         0x05 => cmd("DCR B"),
-        0x06 => cmd("MVI B"),
+        0x06 => cmd("MVI B,D8"),
         0x07 => cmd("RLC"),
         0x09 => cmd("DAD B"),
         0x0A => cmd("LDAX BC"),
         0x0B => cmd("DCX BC"),
         0x0C => cmd("INR C"),
         0x0D => cmd("DCR C"),
-        0x0E => cmd("MVI C"),
+        0x0E => cmd("MVI C,D8"),
         0x0F => cmd("RRC"),
-        0x11 => cmd("LXI D"),
+        0x11 => cmd("LXI D,D16"),
         0x12 => cmd("STAX (DE)"),
         0x13 => cmd("INX DE"),
         0x14 => cmd("INR D"),
         0x15 => cmd("DCR D"),
-        0x16 => cmd("MVI D"),
+        0x16 => cmd("MVI D,D8"),
         0x17 => cmd("RAL"),
         0x19 => cmd("DAD D"),
         0x1A => cmd("LDAX D"),
         0x1B => cmd("DCX DE"),
         0x1C => cmd("INR E"),
         0x1D => cmd("DCR E"),
-        0x1E => cmd("MVI E"),
+        0x1E => cmd("MVI E,D8"),
         0x1F => cmd("RAR"),
-        0x21 => cmd("LXI H"),
+        0x21 => cmd("LXI H,D16"),
+        0x22 => cmd("SHLD Addr"),
         0x23 => cmd("INX HL"), // INX HL
         0x24 => cmd("INR H"),
         0x25 => cmd("DCR H"),
-        0x26 => cmd("MVI H"), // MVI H, D8
+        0x26 => cmd("MVI H,D8"),
         0x27 => cmd("DAA"),
         0x29 => cmd("DAD H"), // DAD H (HL = HL + HL)
-        0x2A => cmd("LHLD"),
+        0x2A => cmd("LHLD Addr"),
         0x2B => cmd("DCX HL"),
         0x2C => cmd("INR L"),
         0x2D => cmd("DCR L"),
-        0x2E => cmd("MVI L"),  // MVI L, D8
-        0x2F => cmd("CMA"),    // A <= !A
-        0x31 => cmd("LXI SP"), // LXI SP, D16
-        0x32 => cmd("STA (adr)<-A"),
+        0x2E => cmd("MVI L,D8"),
+        0x2F => cmd("CMA"), // A <= !A
+        0x31 => cmd("LXI SP,D16"),
+        0x32 => cmd("STA Addr"),
         0x33 => cmd("INX SP"), // INX SP
         0x34 => cmd("INR (HL)"),
         0x35 => cmd("DCR (HL)"),
-        0x36 => cmd("MVI (HL)"), // MVI (HL), D8
+        0x36 => cmd("MVI (HL),D8"),
         0x37 => cmd("STC"),
         0x39 => cmd("DAD SP"),
-        0x3A => cmd("LDA (adr)"),
+        0x3A => cmd("LDA Addr"),
         0x3B => cmd("DCX SP"),
         0x3C => cmd("INR A"),
         0x3D => cmd("DCR A"),
-        0x3E => cmd("MVI A"),
+        0x3E => cmd("MVI A,D8"),
         0x3F => cmd("CMC"), // CY <= !CY
         0x40 => cmd("MOV B,B"),
         0x41 => cmd("MOV B,C"),
@@ -222,41 +568,51 @@ pub fn get_opcode_text(op: (u8, u8, u8)) -> Instr {
         0xC0 => cmd("RNZ"),
         0xC1 => cmd("POP B"),
         0xC2 => cmd("JNZ Addr"), // JNZ Addr
-        0xC3 => cmd("JMP"),      // JMP
-        0xC4 => cmd("CNZ"),
+        0xC3 => cmd("JMP Addr"),
+        0xC4 => cmd("CNZ Addr"),
         0xC5 => cmd("PUSH B"), // PUSH B
-        0xC6 => cmd("ADI"),
+        0xC6 => cmd("ADI D8"),
         0xC7 => cmd("RST 0"),
         0xC8 => cmd("RC"),
         0xC9 => cmd("RET"), // RET
         0xCA => cmd("JZ Addr"),
-        0xCC => cmd("CZ"),
+        0xCC => cmd("CZ Addr"),
         0xCD => cmd("CALL Addr"), // CALL Addr
         0xCE => cmd("ACI D8"),
         0xCF => cmd("RST 8"),
         0xD0 => cmd("RNC"),
         0xD1 => cmd("POP D"),
-        0xD3 => cmd("OUT D"),
+        0xD2 => cmd("JNC Addr"),
+        0xD3 => cmd("OUT D8"),
         0xD4 => cmd("CNC Addr"),
         0xD5 => cmd("PUSH D"), // PUSH D
         0xD7 => cmd("RST 2"),
+        0xDA => cmd("JC Addr"),
+        0xDB => cmd("IN D8"),
         0xDC => cmd("CC Addr"),
         0xDF => cmd("RST 3"),
         0xE0 => cmd("RPO"),
         0xE1 => cmd("POP H"),
-        0xE4 => cmd("CPO"),
+        0xE2 => cmd("JPO Addr"),
+        0xE4 => cmd("CPO Addr"),
         0xE5 => cmd("PUSH H"),
         0xE7 => cmd("RST 4"),
         0xE8 => cmd("RPE"),
+        0xE9 => cmd("PCHL"),
+        0xEA => cmd("JPE Addr"),
         0xEB => cmd("XCHG"),
-        0xEC => cmd("CPE"),
+        0xEC => cmd("CPE Addr"),
         0xEF => cmd("RST 5"),
         0xF0 => cmd("RP"),
-        0xF4 => cmd("CP"), // CALL if Plus
+        0xF1 => cmd("POP PSW"),
+        0xF2 => cmd("JP Addr"),
+        0xF4 => cmd("CP Addr"), // CALL if Plus
         0xF5 => cmd("PUSH PSW"),
         0xF7 => cmd("RST 6"),
         0xF8 => cmd("RM"),
-        0xFE => cmd("CPI"),
+        0xFA => cmd("JM Addr"),
+        0xFC => cmd("CM Addr"),
+        0xFE => cmd("CPI D8"),
         0xFF => cmd("RST 7"),
         _ => cmd("UNK"), // UNK
     }
@@ -2,7 +2,10 @@
 #![allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
 mod constants;
 mod cpu;
-mod disassembler;
+pub mod disassembler;
+pub mod manifest;
+pub mod testroms;
+pub mod testutil;
 mod utils;
 
 pub use crate::constants::*;
@@ -10,6 +13,7 @@ pub use crate::cpu::*; //Cpu;
 pub use crate::utils::*;
 
 use clap::{App, Arg};
+use log::{debug, error, info};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
@@ -38,11 +42,10 @@ pub struct Emu {
 
 impl Emu {
     fn new(rom_file: &str) -> Result<Emu, String> {
-        println!("Creating new Emu Object");
+        debug!("Creating new Emu Object");
 
         // Generate our CPU
         let mut cpu = Cpu::new();
-        cpu.set_disassemble(true);
         //cpu.set_nop(true);
 
         // The list of rom files to load for this particular collection/game
@@ -59,7 +62,7 @@ impl Emu {
             }
         }
 
-        println!(
+        info!(
             "Loaded rom file: {} start at: {:#06X} end at: {:#06X}",
             file_to_load,
             dims.0,
@@ -141,16 +144,12 @@ impl Emu {
             i32::from(LINE_SPACE * 2),
         );
 
-        for i in 0..3 {
+        for (i, (addr, val)) in self.cpu.stack_view(3).iter().enumerate() {
             add_display_text(
                 canvas,
-                &format!(
-                    "$[{:04X}] = {:02X}",
-                    self.cpu.sp + i,
-                    self.cpu.memory[(self.cpu.sp + i) as usize]
-                ),
+                &format!("$[{:04X}] = {:02X}", addr, val),
                 i32::from((EMU_WIDTH * CELL_SIZE) + CELL_SIZE),
-                i32::from(LINE_SPACE * (i + 3)),
+                i32::from(LINE_SPACE * (i as u16 + 3)),
             );
         }
 
@@ -227,19 +226,18 @@ impl Emu {
                     self.last_pc = n;
                 }
                 Err(e) => {
-                    return Err(e);
+                    return Err(e.into());
                 }
             }
         }
 
-        // If needed/wanted, call off to the disassembler to print some pretty details
-        if self.cpu.disassemble && tick_happened {
-            if self.cpu.cycle_count == 1 || (self.cpu.cycle_count % 25 == 0) {
-                println!("{}", disassembler::HEADER);
-            }
+        // Keep the on screen disassembly current, and print it too if wanted
+        if tick_happened {
             // Get our disassembler message text as well as our "next" opcode description
             let dt = disassembler::disassemble(&self.cpu, self.last_pc);
-            println!("{}", dt);
+            if let Err(e) = disassembler::write_trace(&self.cpu, &dt, &mut std::io::stdout()) {
+                return Err(format!("Unable to write disassembly: {}", e));
+            }
             self.last_msg = dt;
         }
         Ok(())
@@ -268,9 +266,34 @@ pub fn go() -> Result<(), String> {
         .arg(Arg::from_usage(
             "-c, --count=[COUNT] 'pauses and initiates single step mode on program count <count>'",
         ))
+        .arg(Arg::from_usage(
+            "-d, --disassemble 'prints the disassembly of each instruction as it runs'",
+        ))
+        .arg(Arg::from_usage(
+            "--dump-dis=[FILE] 'writes the disassembly listing of the rom to <FILE> and exits'",
+        ))
         .args_from_usage("<rom> 'The rom file to load and execute'")
         .get_matches();
 
+    // Gather from the command the rom to use; Clap won't let us skip this but we
+    // load INVADERS by default just in case
+    let mut rom_file: String = String::from("INVADERS");
+    if let Some(f) = matches.value_of("rom") {
+        rom_file = String::from(f);
+    }
+
+    let app = Arc::new(Mutex::new(Emu::new(&rom_file)?));
+    let app_clone = Arc::clone(&app);
+
+    // Asked only for a listing, so write it out and stop here,
+    // before any window is opened
+    if let Some(f) = matches.value_of("dump-dis") {
+        let cpu = &app_clone.lock().unwrap().cpu;
+        disassembler::write_listing(cpu, 0, cpu.rom_len(), f)?;
+        info!("Wrote disassembly listing to {}", f);
+        return Ok(());
+    }
+
     // Create a window.
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
@@ -298,25 +321,19 @@ pub fn go() -> Result<(), String> {
 
     // Build our application and include the CLI options if necessary
 
-    // Gather from the command the rom to use; Clap won't let us skip this but we
-    // load INVADERS by default just in case
-    let mut rom_file: String = String::from("INVADERS");
-    if let Some(f) = matches.value_of("rom") {
-        rom_file = String::from(f);
-    }
-
-    let app = Arc::new(Mutex::new(Emu::new(&rom_file)?));
-    let app_clone = Arc::clone(&app);
-
     // If we are in debug mode, set that now
     if matches.is_present("pause") {
-        println!("Setting pause on tick mode; <SPACEBAR> to step; <F1> to toggle;");
+        info!("Setting pause on tick mode; <SPACEBAR> to step; <F1> to toggle;");
         app_clone.lock().unwrap().set_pause_on_tick(true);
     }
 
+    if matches.is_present("disassemble") {
+        app_clone.lock().unwrap().cpu.set_disassemble(true);
+    }
+
     if let Some(c) = matches.value_of("count") {
         if let Ok(r) = i64::from_str_radix(c, 16) {
-            println!("Pause will happen at cycle count: {:#06X}", r);
+            info!("Pause will happen at cycle count: {:#06X}", r);
             app_clone.lock().unwrap().set_pause_on_count(r as usize);
         }
     }
@@ -328,13 +345,13 @@ pub fn go() -> Result<(), String> {
             match app_clone.lock().unwrap().update() {
                 Ok(_) => (),
                 Err(e) => {
-                    println!("Unable to tick: {}", e);
+                    error!("Unable to tick: {}", e);
                     break;
                 }
             }
         }
 
-        println!(
+        info!(
             "Shutting down. Final CPU state:\n{}",
             app_clone.lock().unwrap().cpu
         );
@@ -347,7 +364,7 @@ pub fn go() -> Result<(), String> {
 
         // If the cpu is not alive, we should just bail as well.
         if !cpu_alive_clone.load(Ordering::Relaxed) {
-            println!("CPU is not alive.  Shutting application down.");
+            info!("CPU is not alive.  Shutting application down.");
             break 'running;
         }
 
@@ -1,4 +1,8 @@
 fn main() -> Result<(), String> {
+    // Without the env_logger feature nothing is listening, so the log is quiet
+    #[cfg(feature = "env_logger")]
+    env_logger::init();
+
     lib::go()?;
     Ok(())
 }
@@ -0,0 +1,64 @@
+//! Reads a manifest of several small programs to be loaded at once, for test
+//! suites that bundle them together.  The manifest is TOML, one ``[[program]]``
+//! table per program giving its ``name``, ``load_addr`` and ``file``, the file
+//! relative to the manifest:
+//!
+//! ```toml
+//! [[program]]
+//! name = "first"
+//! load_addr = 0x0000
+//! file = "first.bin"
+//! ```
+
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    program: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    load_addr: usize,
+    file: String,
+}
+
+/// Parses manifest ``text`` into (name, load address, file) entries
+///
+/// # Errors
+/// Will return an error if the text isn't TOML, or an entry is missing a
+/// field or has one of the wrong type
+pub fn parse(text: &str) -> Result<Vec<(String, usize, String)>, String> {
+    let manifest: Manifest = toml::from_str(text).map_err(|e| format!("Manifest: {}", e))?;
+
+    Ok(manifest
+        .program
+        .into_iter()
+        .map(|e| (e.name, e.load_addr, e.file))
+        .collect())
+}
+
+/// Reads the manifest at ``path`` and each file it lists, ready for
+/// ``Cpu::load_manifest``
+///
+/// # Errors
+/// Will return an error if the manifest or any of its files can't be read, or
+/// if the manifest is malformed
+pub fn read(path: &str) -> Result<Vec<(String, usize, Vec<u8>)>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read manifest {}: {}", path, e))?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    parse(&text)?
+        .into_iter()
+        .map(|(name, addr, file)| {
+            let file = dir.join(file);
+            let bytes = std::fs::read(&file)
+                .map_err(|e| format!("Unable to read {}: {}", file.display(), e))?;
+            Ok((name, addr, bytes))
+        })
+        .collect()
+}
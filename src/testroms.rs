@@ -0,0 +1,31 @@
+//! A small corpus of canned programs, for examples and tests which want a
+//! known bit of 8080 code rather than a magic byte array of their own.  Each
+//! is meant to be loaded at 0x0000 and, apart from BENCHMARK, ends in HLT.
+
+// MVI A,0x42; HLT
+// Leaves 0x42 in A after two instructions.
+pub const MVI_HLT: &[u8] = &[0x3E, 0x42, 0x76];
+
+// MVI B,0x0A; loop: DCR B; JNZ loop; HLT
+// Counts B down from 10 to 0, taking 22 instructions to reach HLT.
+pub const COUNT_LOOP: &[u8] = &[0x06, 0x0A, 0x05, 0xC2, 0x02, 0x00, 0x76];
+
+// LXI SP,0x2400; LXI H,0x1234; PUSH H; POP D; HLT
+// Moves HL into DE by way of the stack, leaving SP back at 0x2400.
+pub const STACK_EXERCISE: &[u8] = &[0x31, 0x00, 0x24, 0x21, 0x34, 0x12, 0xE5, 0xD1, 0x76];
+
+// loop: INX B; DCR A; JMP loop
+// Never halts; a tight loop for timing the CPU with Cpu::run_for_benchmark.
+pub const BENCHMARK: &[u8] = &[0x03, 0x3D, 0xC3, 0x00, 0x00];
+
+// Returns the test ROM with the given name, as spelled above
+#[must_use]
+pub fn by_name(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "MVI_HLT" => Some(MVI_HLT),
+        "COUNT_LOOP" => Some(COUNT_LOOP),
+        "STACK_EXERCISE" => Some(STACK_EXERCISE),
+        "BENCHMARK" => Some(BENCHMARK),
+        _ => None,
+    }
+}
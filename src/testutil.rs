@@ -0,0 +1,106 @@
+pub use crate::cpu::*;
+
+// The most ticks run_program will take before giving back the CPU
+pub const RUN_PROGRAM_LIMIT: usize = 10_000;
+
+// Where replay_and_compare first found the CPU disagreeing with the reference
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub index: usize,       // Which instruction (counting from 0) it happened after
+    pub diffs: Vec<String>, // One "field: expected X, got Y" for each field that differed
+}
+
+// Lists each field which differs between the two snapshots
+fn diff_snapshots(expected: &RegistersSnapshot, actual: &RegistersSnapshot) -> Vec<String> {
+    let fields = [
+        ("pc", expected.pc, actual.pc),
+        ("sp", expected.sp, actual.sp),
+        ("a", expected.a.into(), actual.a.into()),
+        ("b", expected.b.into(), actual.b.into()),
+        ("c", expected.c.into(), actual.c.into()),
+        ("d", expected.d.into(), actual.d.into()),
+        ("e", expected.e.into(), actual.e.into()),
+        ("h", expected.h.into(), actual.h.into()),
+        ("l", expected.l.into(), actual.l.into()),
+        ("flags", expected.flags.into(), actual.flags.into()),
+    ];
+
+    fields
+        .iter()
+        .filter(|(_, e, a)| e != a)
+        .map(|(name, e, a)| format!("{}: expected {:#06X}, got {:#06X}", name, e, a))
+        .collect()
+}
+
+/// Loads ``rom`` at 0x0000, then ticks once for each entry in ``reference``,
+/// comparing the registers after each instruction against it.
+///
+/// # Errors
+/// Returns the first instruction whose registers did not match, or which
+/// failed to run at all.
+pub fn replay_and_compare(rom: &[u8], reference: &[RegistersSnapshot]) -> Result<(), Mismatch> {
+    let mut cpu = Cpu::new();
+    cpu.memory[..rom.len()].copy_from_slice(rom);
+
+    for (index, expected) in reference.iter().enumerate() {
+        if let Err(e) = cpu.tick() {
+            return Err(Mismatch {
+                index,
+                diffs: vec![e.to_string()],
+            });
+        }
+
+        let diffs = diff_snapshots(expected, &cpu.snapshot());
+        if !diffs.is_empty() {
+            return Err(Mismatch { index, diffs });
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads ``rom`` at 0x0000 and ticks it alongside ``reference_stepper``, which
+/// is called with each instruction's index and gives the registers expected
+/// after it, or None once the reference has nothing more to compare.
+///
+/// Returns the index of the first instruction after which the registers
+/// differed, along with the expected and actual snapshots. An instruction
+/// which fails to run counts as a divergence. None if the whole run matched.
+pub fn find_divergence<F>(
+    rom: &[u8],
+    mut reference_stepper: F,
+) -> Option<(usize, RegistersSnapshot, RegistersSnapshot)>
+where
+    F: FnMut(usize) -> Option<RegistersSnapshot>,
+{
+    let mut cpu = Cpu::new();
+    cpu.memory[..rom.len()].copy_from_slice(rom);
+
+    let mut index = 0;
+    while let Some(expected) = reference_stepper(index) {
+        let ran = cpu.tick().is_ok();
+        let actual = cpu.snapshot();
+        if !ran || actual != expected {
+            return Some((index, expected, actual));
+        }
+        index += 1;
+    }
+
+    None
+}
+
+/// Loads ``program`` at 0x0000 of a fresh CPU and runs it until HLT, or for
+/// at most ``RUN_PROGRAM_LIMIT`` ticks, then hands back the CPU to be checked.
+/// Lets an opcode test be written as a short program.
+///
+/// # Panics
+/// Will panic if any instruction fails to run
+#[must_use]
+pub fn run_program(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(program);
+    if let Err(e) = cpu.run_quantum(RUN_PROGRAM_LIMIT) {
+        panic!("run_program failed at {:#06X}: {}", cpu.pc, e);
+    }
+    cpu
+}
@@ -18,3 +18,13 @@ pub fn get_parity(v: u16) -> bool {
 pub fn get_sign(x: u8) -> bool {
     (0b1000_0000 & x) != 0
 }
+
+// Bytes on the stack between base and sp, for a stack growing down from base.
+// Taken with wrapping arithmetic so a stack starting at 0x0000 (and so living
+// just under 0xFFFF) still counts up from zero; negative means sp has been
+// popped past base.
+#[must_use]
+#[allow(clippy::cast_possible_wrap)]
+pub fn stack_depth(base: u16, sp: u16) -> i16 {
+    base.wrapping_sub(sp) as i16
+}
@@ -67,6 +67,18 @@ fn test_get_parity() {
     assert_eq!(lib::get_parity(0b0000), true);
 }
 
+// Pins parity against a bit by bit count for every byte value
+#[test]
+fn test_get_parity_all_bytes() {
+    for v in 0..=0xFF_u16 {
+        let ones = (0..8).filter(|b| v & (1 << b) != 0).count();
+        assert_eq!(lib::get_parity(v), ones % 2 == 0, "parity of {:#04X}", v);
+    }
+    assert_eq!(lib::get_parity(0xFF), true);
+    assert_eq!(lib::get_parity(0x01), false);
+    assert_eq!(lib::get_parity(0x80), false);
+}
+
 #[test]
 fn test_get_addr_pointer() {
     let mut cpu = Cpu::new();
@@ -84,6 +96,12 @@ fn test_get_sign() {
     assert_eq!(get_sign(0b0111_0000), false);
     assert_eq!(get_sign(0b1000u8), false);
     assert_eq!(get_sign(0b1000 << 4), true);
+
+    // Either side of the boundary
+    assert_eq!(get_sign(0x00), false);
+    assert_eq!(get_sign(0x7F), false);
+    assert_eq!(get_sign(0x80), true);
+    assert_eq!(get_sign(0xFF), true);
 }
 
 #[test]
@@ -141,6 +159,222 @@ fn test_op_00() {
     assert_eq!(cpu.pc, op + lib::OPCODE_SIZE);
 }
 
+// The undocumented NOPs are all single byte, even with data-like bytes behind them
+#[test]
+fn test_op_nop_variants() {
+    for &op in &[0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38] {
+        let mut cpu = Cpu::new();
+        cpu.memory[0..3].copy_from_slice(&[op, 0xFF, 0xFF]);
+        cpu.apply_state([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07], 0b1101_0111);
+        cpu.sp = 0x2400;
+
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.pc,
+            lib::OPCODE_SIZE,
+            "{:#04X} should advance PC by 1",
+            op
+        );
+        assert_eq!(lib::disassembler::get_opcode_size(op), 1);
+        assert_eq!(
+            (cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l),
+            (0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07)
+        );
+        assert_eq!(cpu.flags, 0b1101_0111);
+        assert_eq!(cpu.sp, 0x2400);
+    }
+}
+
+#[test]
+fn test_load_registers() {
+    let mut cpu = Cpu::new();
+    cpu.apply_state([0x80, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06], 0b1101_0111);
+    cpu.pc = 0x1234;
+    cpu.sp = 0x2400;
+    let original = cpu.snapshot();
+
+    cpu.apply_state([0xFF; 7], 0b0000_0010);
+    cpu.pc = 0x0000;
+    cpu.sp = 0xFFFF;
+    assert_ne!(cpu.snapshot(), original);
+
+    cpu.load_registers(&original);
+    assert_eq!(cpu.snapshot(), original);
+}
+
+// 0x20 and 0x30 are RIM and SIM on the 8085, but on the 8080 (and so for
+// Space Invaders) they must stay plain NOPs
+#[test]
+fn test_op_20_30() {
+    for &op in &[0x20, 0x30] {
+        let mut cpu = Cpu::new();
+        cpu.apply_state([0x80, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06], 0b0000_0010);
+        cpu.sp = 0x2400;
+        let before = cpu.snapshot();
+
+        cpu.run_opcode((op, 0x00, 0x00)).unwrap();
+        assert_eq!(cpu.pc, lib::OPCODE_SIZE);
+        assert_eq!(
+            cpu.snapshot(),
+            RegistersSnapshot {
+                pc: lib::OPCODE_SIZE as u16,
+                ..before
+            }
+        );
+    }
+}
+
+#[test]
+fn test_rim_sim_8085() {
+    let mut cpu = Cpu::new();
+    cpu.set_cpu_mode(CpuMode::I8085);
+
+    // SIM with mask set enable stores the masks, RIM reads them back
+    cpu.a = 0b0000_1101;
+    cpu.run_opcode((0x30, 0x00, 0x00)).unwrap();
+    cpu.a = 0xFF;
+    cpu.run_opcode((0x20, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0b0000_0101);
+    assert_eq!(cpu.pc, lib::OPCODE_SIZE * 2);
+
+    // Without mask set enable the masks are left alone
+    cpu.a = 0b0000_0010;
+    cpu.run_opcode((0x30, 0x00, 0x00)).unwrap();
+    cpu.run_opcode((0x20, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0b0000_0101);
+
+    // Back in 8080 mode they are NOPs again
+    cpu.set_cpu_mode(CpuMode::I8080);
+    cpu.a = 0xAA;
+    cpu.run_opcode((0x20, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0xAA);
+}
+
+#[test]
+fn test_8085_overflow_flags() {
+    let mut cpu = Cpu::new();
+    cpu.set_cpu_mode(CpuMode::I8085);
+    let v_x5 = |cpu: &Cpu| {
+        (
+            cpu.get_flags() & lib::FLAG_OVERFLOW != 0,
+            cpu.get_flags() & lib::FLAG_X5 != 0,
+        )
+    };
+
+    // ADD B: 0x7F + 0x01 overflows into a negative result, so X5 = V ^ S is clear
+    cpu.a = 0x7F;
+    cpu.b = 0x01;
+    cpu.run_opcode((0x80, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(v_x5(&cpu), (true, false));
+
+    // ADD B: 0x01 + 0x01 clears both
+    cpu.a = 0x01;
+    cpu.run_opcode((0x80, 0x00, 0x00)).unwrap();
+    assert_eq!(v_x5(&cpu), (false, false));
+
+    // SUB B: 0x80 - 0x01 overflows into a positive result
+    cpu.a = 0x80;
+    cpu.run_opcode((0x90, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x7F);
+    assert_eq!(v_x5(&cpu), (true, true));
+
+    // CMP B: 0x01 < 0x02 signed, no overflow but X5 follows the sign
+    cpu.a = 0x01;
+    cpu.b = 0x02;
+    cpu.run_opcode((0xB8, 0x00, 0x00)).unwrap();
+    assert_eq!(v_x5(&cpu), (false, true));
+
+    // INR B from 0x7F and DCR C from 0x80 both overflow
+    cpu.b = 0x7F;
+    cpu.run_opcode((0x04, 0x00, 0x00)).unwrap();
+    assert_eq!(v_x5(&cpu), (true, false));
+    cpu.c = 0x80;
+    cpu.run_opcode((0x0D, 0x00, 0x00)).unwrap();
+    assert_eq!(v_x5(&cpu), (true, true));
+
+    // INX and DCX set X5 only when the pair wraps
+    cpu.b = 0xFF;
+    cpu.c = 0xFF;
+    cpu.run_opcode((0x03, 0x00, 0x00)).unwrap();
+    assert!(cpu.get_flags() & lib::FLAG_X5 != 0);
+    cpu.run_opcode((0x03, 0x00, 0x00)).unwrap();
+    assert!(cpu.get_flags() & lib::FLAG_X5 == 0);
+    cpu.d = 0x00;
+    cpu.e = 0x00;
+    cpu.run_opcode((0x1B, 0x00, 0x00)).unwrap();
+    assert!(cpu.get_flags() & lib::FLAG_X5 != 0);
+
+    // In 8080 mode bit 1 stays set and bit 5 stays clear
+    let mut cpu = Cpu::new();
+    cpu.a = 0x7F;
+    cpu.b = 0x01;
+    cpu.run_opcode((0x80, 0x00, 0x00)).unwrap();
+    cpu.run_opcode((0x90, 0x00, 0x00)).unwrap();
+    cpu.d = 0x00;
+    cpu.e = 0x00;
+    cpu.run_opcode((0x1B, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.get_flags() & 0b0010_0010, 0b0000_0010);
+}
+
+#[test]
+fn test_8085_overflow_with_carry_in() {
+    let mut cpu = Cpu::new();
+    cpu.set_cpu_mode(CpuMode::I8085);
+    let v = |cpu: &Cpu| cpu.get_flags() & lib::FLAG_OVERFLOW != 0;
+
+    // SBB B: 0x00 - 0x7F - 1 = 0x80 is -128, in range, so no overflow
+    cpu.a = 0x00;
+    cpu.b = 0x7F;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0x98, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x80);
+    assert!(!v(&cpu));
+    assert!(cpu.get_flags() & lib::FLAG_X5 != 0);
+
+    // SBB B: 0x80 - 0x00 - 1 = 0x7F overflows
+    cpu.a = 0x80;
+    cpu.b = 0x00;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0x98, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x7F);
+    assert!(v(&cpu));
+
+    // ADC B: 0x7F + 0x00 + 1 = 0x80 overflows
+    cpu.a = 0x7F;
+    cpu.b = 0x00;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0x88, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x80);
+    assert!(v(&cpu));
+
+    // ADC B: 0x80 + 0x7F + 1 = 0x00 with a carry out, but no overflow
+    cpu.a = 0x80;
+    cpu.b = 0x7F;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0x88, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x00);
+    assert!(!v(&cpu));
+    assert!(cpu.test_flag(lib::FLAG_CARRY));
+
+    // ADC B: 0x7F + 0xFF + 1 = 0x7F with a carry out, no overflow
+    cpu.a = 0x7F;
+    cpu.b = 0xFF;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0x88, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x7F);
+    assert!(!v(&cpu));
+    assert!(cpu.test_flag(lib::FLAG_CARRY));
+
+    // ACI 0xFF with carry set works the same way
+    cpu.a = 0x7F;
+    cpu.set_flag(lib::FLAG_CARRY);
+    cpu.run_opcode((0xCE, 0xFF, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x7F);
+    assert!(!v(&cpu));
+    assert!(cpu.test_flag(lib::FLAG_CARRY));
+}
+
 #[test]
 fn test_op_03() {
     let mut cpu = Cpu::new();
@@ -198,6 +432,24 @@ fn test_op_inr() {
     assert_eq!(cpu.pc, op + lib::OPCODE_SIZE);
 }
 
+// Aux carry for INR E must come from E, not D
+#[test]
+fn test_op_inr_e_aux_carry() {
+    let mut cpu = Cpu::new();
+
+    cpu.e = 0x0F;
+    cpu.d = 0x00; // Would not carry if it were used
+    cpu.run_opcode((0x1C, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.e, 0x10);
+    assert!(cpu.test_flag(lib::FLAG_AUXCARRY));
+
+    cpu.e = 0x01;
+    cpu.d = 0x0F; // Would carry if it were used
+    cpu.run_opcode((0x1C, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.e, 0x02);
+    assert!(!cpu.test_flag(lib::FLAG_AUXCARRY));
+}
+
 #[test]
 fn test_op_35() {
     let mut cpu = Cpu::new();
@@ -555,6 +807,57 @@ fn test_op_jz() {
     assert_eq!(cpu.pc, op + (lib::OPCODE_SIZE * 3));
 }
 
+// Each conditional jump, as (opcode, flag, jumps when the flag is set)
+#[test]
+fn test_op_jmp_if() {
+    let jumps = [
+        (0xD2, lib::FLAG_CARRY, false),  // JNC
+        (0xDA, lib::FLAG_CARRY, true),   // JC
+        (0xE2, lib::FLAG_PARITY, false), // JPO
+        (0xEA, lib::FLAG_PARITY, true),  // JPE
+        (0xF2, lib::FLAG_SIGN, false),   // JP
+        (0xFA, lib::FLAG_SIGN, true),    // JM
+    ];
+
+    for &(opcode, flag, when_set) in &jumps {
+        let mut cpu = Cpu::new();
+        let op = cpu.pc;
+
+        // Taken
+        if when_set {
+            cpu.set_flag(flag);
+        } else {
+            cpu.reset_flag(flag);
+        }
+        cpu.run_opcode((opcode, 0x34, 0x12)).unwrap();
+        assert_eq!(cpu.pc, 0x1234, "{:#04X} should jump", opcode);
+
+        // Not taken
+        cpu.pc = op;
+        if when_set {
+            cpu.reset_flag(flag);
+        } else {
+            cpu.set_flag(flag);
+        }
+        cpu.run_opcode((opcode, 0x34, 0x12)).unwrap();
+        assert_eq!(
+            cpu.pc,
+            op + (lib::OPCODE_SIZE * 3),
+            "{:#04X} should fall through",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn test_op_pchl() {
+    let mut cpu = Cpu::new();
+    cpu.h = 0x41;
+    cpu.l = 0x3E;
+    cpu.run_opcode((0xE9, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x413E);
+}
+
 #[test]
 fn test_op_c3() {
     let mut cpu = Cpu::new();
@@ -586,6 +889,33 @@ fn test_op_c5() {
     assert_eq!(cpu.pc, pc + lib::OPCODE_SIZE);
 }
 
+#[test]
+fn test_stack_view() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x2400;
+    cpu.b = 0x12;
+    cpu.c = 0x34;
+    cpu.d = 0x56;
+    cpu.e = 0x78;
+
+    cpu.run_opcode((0xC5, 0x00, 0x00)).unwrap(); // PUSH B
+    cpu.run_opcode((0xD5, 0x00, 0x00)).unwrap(); // PUSH D
+
+    assert_eq!(
+        cpu.stack_view(4),
+        vec![
+            (0x23FC, 0x78),
+            (0x23FD, 0x56),
+            (0x23FE, 0x34),
+            (0x23FF, 0x12)
+        ]
+    );
+
+    // The view wraps around the top of memory
+    cpu.sp = 0xFFFF;
+    assert_eq!(cpu.stack_view(2)[1].0, 0x0000);
+}
+
 // POP from the stack to regiser pair HL
 // 	L <- (sp); H <- (sp+1); sp <- sp+2
 #[test]
@@ -683,7 +1013,7 @@ fn test_op_ret() {
     cpu.memory[usize::from(cpu.sp + 1)] = 0x10; // HI
 
     cpu.run_opcode((0xC9, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
     assert_eq!(cpu.sp, 0x2402);
 
     // Try a return if zero flag is zero
@@ -693,7 +1023,7 @@ fn test_op_ret() {
     cpu.memory[usize::from(cpu.sp + 1)] = 0x10; // HI
     cpu.reset_flag(lib::FLAG_ZERO);
     cpu.run_opcode((0xC0, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
 }
 
 #[test]
@@ -716,7 +1046,7 @@ fn test_op_rpo() {
     // Try again with partity odd (parity flag = 0)
     cpu.reset_flag(lib::FLAG_PARITY); // ODD parity = false(0)
     cpu.run_opcode((0xE0, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
     assert_eq!(cpu.sp, 0x2402);
 }
 
@@ -740,7 +1070,7 @@ fn test_op_rpe() {
     // try a return with parity NOT odd (parity flag = 1)
     cpu.set_flag(lib::FLAG_PARITY); // EVEN Parity = true(1)
     cpu.run_opcode((0xE8, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
     assert_eq!(cpu.sp, 0x2402);
 }
 
@@ -762,7 +1092,7 @@ fn test_op_rm() {
 
     cpu.set_flag(lib::FLAG_SIGN);
     cpu.run_opcode((0xF8, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
     assert_eq!(cpu.sp, 0x2402);
 }
 
@@ -784,7 +1114,7 @@ fn test_op_rp() {
 
     cpu.reset_flag(lib::FLAG_SIGN); // true = minus
     cpu.run_opcode((0xF0, 0x00, 0x00)).unwrap();
-    assert_eq!(cpu.pc, 0x1032 + lib::OPCODE_SIZE * 3);
+    assert_eq!(cpu.pc, 0x1032);
     assert_eq!(cpu.sp, 0x2402);
 }
 
@@ -798,9 +1128,9 @@ fn test_op_cd() {
     // Pretend we are going to CALL addr of 0x0503
     cpu.run_opcode((0xCD, 0x03, 0x05)).unwrap();
 
-    // memory should be set now
+    // memory should be set now, to the address just past the CALL
     assert_eq!(cpu.memory[0x23FF], 0x18_u8);
-    assert_eq!(cpu.memory[0x23FE], 0xD9_u8);
+    assert_eq!(cpu.memory[0x23FE], 0xDC_u8);
 
     // Check stack pointer
     assert_eq!(cpu.sp, 0x23FE);
@@ -830,9 +1160,9 @@ fn test_cc_addr() {
     cpu.set_flag(lib::FLAG_CARRY);
     cpu.run_opcode((0xDC, 0x03, 0x05)).unwrap();
 
-    // memory should be set now
+    // memory should be set now, to the address just past the CALL
     assert_eq!(cpu.memory[0x23FF], 0x18_u8);
-    assert_eq!(cpu.memory[0x23FE], 0xD9_u8);
+    assert_eq!(cpu.memory[0x23FE], 0xDC_u8);
 
     // Check stack pointer
     assert_eq!(cpu.sp, 0x23FE);
@@ -963,6 +1293,68 @@ fn test_op_09() {
     assert_eq!(cpu.pc, op + (lib::OPCODE_SIZE));
 }
 
+// DAD only ever touches carry, which follows the 16 bit overflow
+#[test]
+fn test_op_dad_flags() {
+    let mut cpu = Cpu::new();
+    let others = lib::FLAG_SIGN | lib::FLAG_ZERO | lib::FLAG_AUXCARRY | lib::FLAG_PARITY;
+    cpu.flags = 0b0000_0010 | others;
+
+    cpu.b = 0x80;
+    cpu.c = 0x01;
+    cpu.h = 0x80;
+    cpu.l = 0x00;
+    cpu.run_opcode((0x09, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.get_register_pair(Registers::HL), 0x0001);
+    assert_eq!(cpu.test_flag(lib::FLAG_CARRY), true);
+    assert_eq!(cpu.flags & others, others);
+
+    cpu.run_opcode((0x09, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.get_register_pair(Registers::HL), 0x8002);
+    assert_eq!(cpu.test_flag(lib::FLAG_CARRY), false);
+    assert_eq!(cpu.flags & others, others);
+}
+
+#[test]
+fn test_last_cycles() {
+    let mut cpu = Cpu::new();
+    // INX B; DAD B; DCX B; HLT
+    cpu.memory[0..4].copy_from_slice(&[0x03, 0x09, 0x0B, 0x76]);
+
+    cpu.tick().unwrap();
+    assert_eq!(cpu.last_cycles, 5);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.last_cycles, 10);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.last_cycles, 5);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.last_cycles, 7);
+}
+
+#[test]
+fn test_call_ret_round_trip() {
+    let mut cpu = Cpu::new();
+    // 0x0100: CALL 0x0200; 0x0200: RET
+    cpu.memory[0x0100..0x0103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+    cpu.memory[0x0200] = 0xC9;
+    cpu.pc = 0x0100;
+    cpu.sp = 0x2400;
+
+    cpu.tick().unwrap();
+    let mut cycles = cpu.last_cycles;
+    assert_eq!(cpu.pc, 0x0200);
+    assert_eq!(cpu.sp, 0x23FE);
+    // The return address, just past the CALL, is what gets pushed
+    assert_eq!(cpu.memory[0x23FF], 0x01);
+    assert_eq!(cpu.memory[0x23FE], 0x03);
+
+    cpu.tick().unwrap();
+    cycles += cpu.last_cycles;
+    assert_eq!(cpu.pc, 0x0103);
+    assert_eq!(cpu.sp, 0x2400);
+    assert_eq!(cycles, 17 + 10);
+}
+
 #[test]
 fn test_rst() {
     let mut cpu = Cpu::new();
@@ -971,9 +1363,105 @@ fn test_rst() {
 
     cpu.run_opcode((0xFF, 0x00, 0x00)).unwrap();
     assert_eq!(cpu.sp, 0x23FE);
-    assert_eq!(cpu.memory[0x23FE], 0x12); // High half
-    assert_eq!(cpu.memory[0x23FF], 0x34); // Low half
+    // The return address is the instruction after the RST
+    assert_eq!(cpu.memory[0x23FE], 0x35); // Low half
+    assert_eq!(cpu.memory[0x23FF], 0x12); // High half
     assert_eq!(cpu.pc, 0x38);
+
+    // RET comes back to the instruction after the RST
+    cpu.run_opcode((0xC9, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x1235);
+    assert_eq!(cpu.sp, 0x2400);
+}
+
+#[test]
+fn test_op_rst_range() {
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x1234;
+    cpu.sp = 0x2400;
+
+    match cpu.op_rst(2).unwrap() {
+        ProgramCounter::Jump(d) => assert_eq!(d, 0x10),
+        _ => panic!("RST 2 should jump"),
+    }
+    assert_eq!(cpu.sp, 0x23FE);
+    assert_eq!(cpu.memory[0x23FE], 0x35);
+    assert_eq!(cpu.memory[0x23FF], 0x12);
+
+    // Out of range, nothing should be pushed
+    assert!(matches!(cpu.op_rst(8), Err(CpuError::InvalidVector(8))));
+    assert_eq!(cpu.sp, 0x23FE);
+
+    // What was pushed is something RET can come back from
+    cpu.pc = 0x10;
+    cpu.run_opcode((0xC9, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x1235);
+    assert_eq!(cpu.sp, 0x2400);
+}
+
+#[test]
+fn test_force_interrupt() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x2400;
+
+    // Halt the CPU, which leaves PC just past the HLT
+    cpu.run_opcode((0x76, 0x00, 0x00)).unwrap();
+    let halted_pc = cpu.pc;
+    assert_eq!(cpu.nop, true);
+
+    cpu.force_interrupt(2).unwrap();
+    assert_eq!(cpu.pc, 0x10);
+    assert_eq!(cpu.sp, 0x23FE);
+    assert_eq!(cpu.nop, false);
+    assert_eq!(cpu.peek_u16(0x23FE), halted_pc as u16);
+
+    // A bad vector leaves everything as it was
+    assert!(cpu.force_interrupt(8).is_err());
+    assert_eq!(cpu.pc, 0x10);
+    assert_eq!(cpu.sp, 0x23FE);
+}
+
+#[test]
+fn test_force_interrupt_returns() {
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x1234;
+    cpu.sp = 0x2400;
+    cpu.memory[0x10] = 0xC9; // RET at the RST 2 vector
+
+    cpu.force_interrupt(2).unwrap();
+    assert_eq!(cpu.pc, 0x10);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.pc, 0x1234);
+    assert_eq!(cpu.sp, 0x2400);
+
+    // With SP still 0 on a fresh CPU, the push wraps instead of panicking
+    let mut cpu = Cpu::new();
+    cpu.force_interrupt(1).unwrap();
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert_eq!(cpu.pc, 0x08);
+}
+
+#[test]
+fn test_interrupt_handler_reads_stacked_pc() {
+    // A handler at the RST 7 vector that takes the return address with POP H
+    let mut cpu = Cpu::new();
+    cpu.memory[0x38] = 0xE1; // POP H
+    cpu.memory[0x1234] = 0xFF; // RST 7
+    cpu.pc = 0x1234;
+    cpu.sp = 0x2400;
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    assert_eq!(cpu.get_register_pair(Registers::HL), 0x1235);
+    assert_eq!(cpu.sp, 0x2400);
+
+    // An interrupt stacks the PC it broke in on, not the one after it
+    let mut cpu = Cpu::new();
+    cpu.memory[0x10] = 0xE1; // POP H
+    cpu.pc = 0x0456;
+    cpu.sp = 0x2400;
+    cpu.force_interrupt(2).unwrap();
+    cpu.tick().unwrap();
+    assert_eq!(cpu.get_register_pair(Registers::HL), 0x0456);
 }
 
 #[test]
@@ -1031,6 +1519,21 @@ fn test_sbb() {
     assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), true);
 }
 
+#[test]
+fn test_sbb_carry_in_wraps() {
+    // 0x00 - 0xFF - 1 borrows even though 0xFF + 1 doesn't fit in a byte
+    let mut cpu = Cpu::new();
+    cpu.a = 0x00;
+    cpu.b = 0xFF;
+    cpu.set_flag(lib::FLAG_CARRY);
+
+    cpu.run_opcode((0x98, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.test_flag(lib::FLAG_CARRY), true);
+    assert_eq!(cpu.test_flag(lib::FLAG_ZERO), true);
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), false);
+}
+
 #[test]
 fn test_lhld() {
     let mut cpu = Cpu::new();
@@ -1044,6 +1547,41 @@ fn test_lhld() {
     assert_eq!(cpu.pc, op + (lib::OPCODE_SIZE * 3));
 }
 
+#[test]
+fn test_lhld_wrap() {
+    let mut cpu = Cpu::new();
+
+    cpu.memory[0xFFFF] = 0x34;
+    cpu.memory[0x0000] = 0x12;
+    cpu.run_opcode((0x2A, 0xFF, 0xFF)).unwrap();
+    assert_eq!(cpu.l, 0x34);
+    assert_eq!(cpu.h, 0x12);
+}
+
+#[test]
+fn test_shld() {
+    let mut cpu = Cpu::new();
+    let op = cpu.pc;
+
+    cpu.h = 0xAE;
+    cpu.l = 0x29;
+    cpu.run_opcode((0x22, 0x0A, 0x01)).unwrap();
+    assert_eq!(cpu.memory[0x10A], 0x29);
+    assert_eq!(cpu.memory[0x10B], 0xAE);
+    assert_eq!(cpu.pc, op + (lib::OPCODE_SIZE * 3));
+}
+
+#[test]
+fn test_shld_wrap() {
+    let mut cpu = Cpu::new();
+
+    cpu.h = 0x12;
+    cpu.l = 0x34;
+    cpu.run_opcode((0x22, 0xFF, 0xFF)).unwrap();
+    assert_eq!(cpu.memory[0xFFFF], 0x34);
+    assert_eq!(cpu.memory[0x0000], 0x12);
+}
+
 #[test]
 fn test_dcx() {
     let mut cpu = Cpu::new();
@@ -1062,6 +1600,30 @@ fn test_dcx() {
     assert_eq!(cpu.sp, 0x1233);
 }
 
+// INX and DCX affect no flags, even when the pair wraps around
+#[test]
+fn test_inx_dcx_flags() {
+    let incs = [0x03, 0x13, 0x23, 0x33]; // INX B, D, H, SP
+    let decs = [0x0B, 0x1B, 0x2B, 0x3B]; // DCX B, D, H, SP
+
+    for &flags in &[0b1101_0111, 0b0000_0010] {
+        for &op in &incs {
+            let mut cpu = Cpu::new();
+            cpu.apply_state([0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], flags);
+            cpu.sp = 0xFFFF;
+            cpu.run_opcode((op, 0x00, 0x00)).unwrap();
+            assert_eq!(cpu.flags, flags, "{:#04X} changed the flags", op);
+        }
+        for &op in &decs {
+            let mut cpu = Cpu::new();
+            cpu.apply_state([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], flags);
+            cpu.sp = 0x0000;
+            cpu.run_opcode((op, 0x00, 0x00)).unwrap();
+            assert_eq!(cpu.flags, flags, "{:#04X} changed the flags", op);
+        }
+    }
+}
+
 #[test]
 fn test_ral() {
     let mut cpu = Cpu::new();
@@ -1300,7 +1862,25 @@ fn test_op_ana() {
     cpu.c = 0x0F;
 
     cpu.run_opcode((0xA1, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x0C);
     assert_eq!(cpu.pc, op + lib::OPCODE_SIZE);
+
+    // Bit 3 of both operands is set, so aux carry is set
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), true);
+
+    // Bit 3 of only one operand is set, still aux carry
+    cpu.a = 0xF0;
+    cpu.c = 0x08;
+    cpu.run_opcode((0xA1, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), true);
+
+    // Bit 3 of neither operand is set
+    cpu.a = 0xF7;
+    cpu.c = 0x37;
+    cpu.run_opcode((0xA1, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.a, 0x37);
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), false);
 }
 
 #[test]
@@ -1317,8 +1897,10 @@ fn test_op_xra() {
 
     cpu.a = 0xFF;
     cpu.b = 0b0000_1010;
+    cpu.set_flag(lib::FLAG_AUXCARRY);
     cpu.run_opcode((0xA8, 0x00, 0x00)).unwrap();
     assert_eq!(cpu.a, 0b1111_0101);
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), false);
 }
 
 #[test]
@@ -1329,11 +1911,13 @@ fn test_op_ora() {
     cpu.a = 0x33;
     cpu.c = 0x0F;
     cpu.set_flag(lib::FLAG_CARRY);
+    cpu.set_flag(lib::FLAG_AUXCARRY);
 
     // Should zero out the A register
     cpu.run_opcode((0xB1, 0x00, 0x00)).unwrap();
     assert_eq!(cpu.a, 0x3F);
     assert_eq!(cpu.test_flag(lib::FLAG_CARRY), false);
+    assert_eq!(cpu.test_flag(lib::FLAG_AUXCARRY), false);
     assert_eq!(cpu.pc, op + lib::OPCODE_SIZE);
 }
 
@@ -1427,3 +2011,826 @@ fn test_op_aci() {
     cpu.run_opcode((0xCE, 0x42, 0x00)).unwrap();
     assert_eq!(cpu.a, 0x57);
 }
+
+#[test]
+fn test_unknown_opcode_error() {
+    let mut cpu = Cpu::new();
+    let op = cpu.pc;
+
+    // 0xCB is not implemented, so by default it is an error
+    assert_eq!(
+        cpu.run_opcode((0xCB, 0x00, 0x00)),
+        Err(CpuError::UnknownOpcode(0xCB))
+    );
+    assert_eq!(cpu.pc, op);
+    assert!(cpu.unknown_opcodes().is_empty());
+}
+
+#[test]
+fn test_cpu_error() {
+    let mut cpu = Cpu::new();
+
+    match cpu.run_opcode((0xCB, 0x00, 0x00)) {
+        Err(CpuError::UnknownOpcode(op)) => assert_eq!(op, 0xCB),
+        _ => panic!("0xCB should be an unknown opcode"),
+    }
+    match cpu.force_interrupt(9) {
+        Err(CpuError::InvalidVector(v)) => assert_eq!(v, 9),
+        _ => panic!("RST 9 should be an invalid vector"),
+    }
+
+    // The messages are unchanged from when these were plain strings
+    let msg: String = CpuError::UnknownOpcode(0xCB).into();
+    assert_eq!(msg, "!! OPCODE: 0xCB 0b11001011 is unknown !!");
+    assert_eq!(
+        CpuError::InvalidVector(9).to_string(),
+        "!! RST: vector 9 is out of range (0..=7) !!"
+    );
+}
+
+#[test]
+fn test_unknown_opcode_nop_and_log() {
+    let mut cpu = Cpu::new();
+    cpu.set_unknown_opcode_policy(UnknownPolicy::NopAndLog);
+    cpu.pc = 0x10;
+
+    cpu.run_opcode((0xCB, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x10 + lib::OPCODE_SIZE);
+    cpu.run_opcode((0xDD, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x10 + lib::OPCODE_SIZE * 2);
+
+    assert_eq!(cpu.unknown_opcodes(), &[(0x10, 0xCB), (0x11, 0xDD)]);
+
+    // Skipping the last byte of memory wraps PC around to 0x0000
+    cpu.pc = 0xFFFF;
+    cpu.run_opcode((0xCB, 0x00, 0x00)).unwrap();
+    assert_eq!(cpu.pc, 0x0000);
+}
+
+#[test]
+fn test_unknown_opcode_logged_once_per_site() {
+    // loop: DB 0xCB; DB 0xDD; JMP loop
+    let program = [0xCB, 0xDD, 0xC3, 0x00, 0x00];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_unknown_opcode_policy(UnknownPolicy::NopAndLog);
+
+    for _ in 0..300 {
+        cpu.tick().unwrap();
+    }
+    assert_eq!(cpu.unknown_opcodes(), &[(0x00, 0xCB), (0x01, 0xDD)]);
+
+    // A different opcode turning up at a logged address is a new entry
+    cpu.memory[0x0000] = 0xED;
+    for _ in 0..3 {
+        cpu.tick().unwrap();
+    }
+    assert_eq!(
+        cpu.unknown_opcodes(),
+        &[(0x00, 0xCB), (0x01, 0xDD), (0x00, 0xED)]
+    );
+}
+
+#[test]
+fn test_cpu_debug() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0x0000] = 0x01;
+    cpu.memory[0x0001] = 0x02;
+    cpu.memory[0x0002] = 0x03;
+    cpu.memory[0x2000] = 0xFF;
+    cpu.pc = 0x1234;
+
+    let dbg = format!("{:?}", cpu);
+    assert!(dbg.contains("pc: 0x1234"));
+    assert!(dbg.contains(&format!("{} bytes", lib::RAM_SIZE)));
+    assert!(dbg.contains("non-zero: [0x0000..0x0003, 0x2000..0x2001]"));
+}
+
+#[test]
+fn test_run_quantum() {
+    // MVI B,0x0A; loop: DCR B; JNZ loop; HLT
+    let program = [0x06, 0x0A, 0x05, 0xC2, 0x02, 0x00, 0x76];
+
+    let mut big = Cpu::new();
+    big.memory[..program.len()].copy_from_slice(&program);
+    assert_eq!(big.run_quantum(1000).unwrap(), RunStopReason::Halted);
+
+    let mut small = Cpu::new();
+    small.memory[..program.len()].copy_from_slice(&program);
+    let mut calls = 0;
+    while small.run_quantum(3).unwrap() == RunStopReason::BudgetExhausted {
+        calls += 1;
+    }
+    assert!(calls > 1);
+
+    assert_eq!(small.cycle_count, big.cycle_count);
+    assert_eq!(small.pc, big.pc);
+    assert_eq!(small.b, 0x00);
+    assert_eq!(big.b, 0x00);
+}
+
+#[test]
+fn test_run_blocking_safety_limit() {
+    // loop: JMP loop
+    let mut cpu = Cpu::new();
+    cpu.memory[0..3].copy_from_slice(&[0xC3, 0x00, 0x00]);
+    cpu.set_safety_limit(1000);
+
+    assert_eq!(cpu.run_blocking(None).unwrap(), RunStopReason::SafetyLimit);
+    assert_eq!(cpu.cycle_count, 1000);
+
+    // An explicit budget still reports running out of budget
+    assert_eq!(
+        cpu.run_blocking(Some(10)).unwrap(),
+        RunStopReason::BudgetExhausted
+    );
+}
+
+#[test]
+fn test_cycle_budget_remaining() {
+    // loop: JMP loop
+    let mut cpu = Cpu::new();
+    cpu.memory[0..3].copy_from_slice(&[0xC3, 0x00, 0x00]);
+    assert_eq!(cpu.cycle_budget_remaining(), None);
+
+    let mut seen: Vec<usize> = Vec::new();
+    let reason = cpu
+        .run_quantum_with(5, |c| seen.push(c.cycle_budget_remaining().unwrap()))
+        .unwrap();
+
+    assert_eq!(reason, RunStopReason::BudgetExhausted);
+    assert_eq!(seen, vec![4, 3, 2, 1, 0]);
+    assert_eq!(cpu.cycle_budget_remaining(), Some(0));
+}
+
+#[test]
+fn test_apply_state() {
+    let mut cpu = Cpu::new();
+    cpu.apply_state([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07], 0b1000_0011);
+    assert_eq!(
+        (cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l),
+        (0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07)
+    );
+    assert_eq!(cpu.flags, 0b1000_0011);
+}
+
+#[cfg(feature = "fuzz")]
+#[test]
+fn test_randomize_from_seed() {
+    let mut first = Cpu::new();
+    let mut second = Cpu::new();
+    first.randomize_from_seed(0x8080);
+    second.randomize_from_seed(0x8080);
+
+    assert_eq!(
+        (first.a, first.b, first.c, first.d, first.e, first.h, first.l),
+        (second.a, second.b, second.c, second.d, second.e, second.h, second.l)
+    );
+    assert_eq!(first.sp, second.sp);
+    assert_eq!(first.flags, second.flags);
+    assert_eq!(
+        first.memory[lib::FUZZ_RAM_START..lib::FUZZ_RAM_END],
+        second.memory[lib::FUZZ_RAM_START..lib::FUZZ_RAM_END]
+    );
+
+    // The fixed flag bits are still fixed
+    assert_eq!(first.flags & 0b0010_1010, 0b0000_0010);
+
+    // And a different seed gives a different state
+    second.randomize_from_seed(0x8085);
+    assert_ne!(
+        first.memory[lib::FUZZ_RAM_START..lib::FUZZ_RAM_END],
+        second.memory[lib::FUZZ_RAM_START..lib::FUZZ_RAM_END]
+    );
+}
+
+#[test]
+fn test_decode_at() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0x10] = 0xC3;
+    cpu.memory[0x11] = 0x34;
+    cpu.memory[0x12] = 0x12;
+    cpu.memory[0x13] = 0x3E;
+    cpu.memory[0x14] = 0x22;
+    cpu.flags = 0b1000_0011;
+    cpu.pc = 0x02;
+
+    let (op, bytes) = cpu.decode_at(0x10);
+    assert_eq!(op, (0xC3, 0x34, 0x12));
+    assert_eq!(bytes, vec![0xC3, 0x34, 0x12]);
+
+    let (op, bytes) = cpu.decode_at(0x13);
+    assert_eq!(op, (0x3E, 0x22, 0x00));
+    assert_eq!(bytes, vec![0x3E, 0x22]);
+
+    // Nothing about the CPU moved
+    assert_eq!(cpu.pc, 0x02);
+    assert_eq!(cpu.flags, 0b1000_0011);
+}
+
+#[test]
+fn test_rom_loaded() {
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.is_rom_loaded(), false);
+    assert_eq!(cpu.rom_len(), 0);
+
+    let path = std::env::temp_dir().join("eightyeighty_test_rom_loaded.COM");
+    std::fs::write(&path, [0x3E, 0x22, 0x76]).unwrap();
+    let (start, end) = cpu
+        .load_rom(path.to_str().unwrap().to_string(), 0x100)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(cpu.is_rom_loaded(), true);
+    assert_eq!(cpu.rom_len(), 3);
+    assert_eq!(end - start, cpu.rom_len());
+    assert_eq!(cpu.memory[0x102], 0x76);
+}
+
+#[test]
+fn test_load_test_rom() {
+    let mut cpu = Cpu::new();
+    cpu.load_test_rom("COUNT_LOOP").unwrap();
+    assert_eq!(cpu.rom_len(), lib::testroms::COUNT_LOOP.len());
+
+    assert_eq!(cpu.run_quantum(100).unwrap(), RunStopReason::Halted);
+    assert_eq!(cpu.b, 0x00);
+    assert_eq!(cpu.cycle_count, 22);
+
+    let mut cpu = Cpu::new();
+    cpu.load_test_rom("STACK_EXERCISE").unwrap();
+    assert_eq!(cpu.run_quantum(100).unwrap(), RunStopReason::Halted);
+    assert_eq!(cpu.get_register_pair(Registers::DE), 0x1234);
+    assert_eq!(cpu.sp, 0x2400);
+
+    assert!(cpu.load_test_rom("NOPE").is_err());
+}
+
+#[test]
+fn test_effective_address() {
+    let mut cpu = Cpu::new();
+    cpu.h = 0x20;
+    cpu.l = 0x10;
+    cpu.sp = 0x2400;
+
+    // LDA 0x1234
+    cpu.memory[0..3].copy_from_slice(&[0x3A, 0x34, 0x12]);
+    assert_eq!(cpu.effective_address(), Some(0x1234));
+
+    // MOV A,M and MOV M,B read and write (HL)
+    cpu.memory[0] = 0x7E;
+    assert_eq!(cpu.effective_address(), Some(0x2010));
+    cpu.memory[0] = 0x70;
+    assert_eq!(cpu.effective_address(), Some(0x2010));
+
+    // PUSH B and RST 1 use the stack
+    cpu.memory[0] = 0xC5;
+    assert_eq!(cpu.effective_address(), Some(0x2400));
+    cpu.memory[0] = 0xCF;
+    assert_eq!(cpu.effective_address(), Some(0x2400));
+
+    // MOV A,B, HLT and JMP don't touch memory
+    cpu.memory[0] = 0x78;
+    assert_eq!(cpu.effective_address(), None);
+    cpu.memory[0] = 0x76;
+    assert_eq!(cpu.effective_address(), None);
+    cpu.memory[0] = 0xC3;
+    assert_eq!(cpu.effective_address(), None);
+
+    // Nothing moved
+    assert_eq!(cpu.pc, 0x0000);
+}
+
+#[test]
+fn test_run_for_benchmark() {
+    let mut cpu = Cpu::new();
+    let ips = cpu.run_for_benchmark(10_000);
+    assert!(ips.is_finite());
+    assert!(ips > 0.0);
+    assert_eq!(cpu.cycle_count, 10_000);
+}
+
+#[test]
+fn test_peek_poke_u16() {
+    let mut cpu = Cpu::new();
+    cpu.poke_u16(0x2000, 0x1234);
+    assert_eq!(cpu.memory[0x2000], 0x34);
+    assert_eq!(cpu.memory[0x2001], 0x12);
+    assert_eq!(cpu.peek_u16(0x2000), 0x1234);
+
+    // The high byte of a word at the top of memory is at 0x0000
+    cpu.poke_u16(0xFFFF, 0xBEEF);
+    assert_eq!(cpu.memory[0xFFFF], 0xEF);
+    assert_eq!(cpu.memory[0x0000], 0xBE);
+    assert_eq!(cpu.peek_u16(0xFFFF), 0xBEEF);
+}
+
+#[test]
+fn test_program_counter_advance() {
+    assert_eq!(ProgramCounter::Next.advance(0x0100), 0x0101);
+    assert_eq!(ProgramCounter::Two.advance(0x0100), 0x0102);
+    assert_eq!(ProgramCounter::Three.advance(0x0100), 0x0103);
+    assert_eq!(ProgramCounter::Jump(0x2000).advance(0x0100), 0x2000);
+
+    // Everything wraps to 16 bits
+    assert_eq!(ProgramCounter::Next.advance(0xFFFF), 0x0000);
+    assert_eq!(ProgramCounter::Three.advance(0xFFFE), 0x0001);
+    assert_eq!(ProgramCounter::Jump(0x1_0010).advance(0x0100), 0x0010);
+}
+
+#[test]
+fn test_three_byte_advance() {
+    // LXI H, SHLD, LHLD, STA and LDA each move PC on by exactly three, both
+    // through run_opcode and through tick
+    for &op in &[0x21, 0x22, 0x2A, 0x32, 0x3A] {
+        assert_eq!(lib::disassembler::get_opcode_size(op), 3);
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.run_opcode((op, 0x00, 0x20)).unwrap();
+        assert_eq!(cpu.pc, 0x0103, "run_opcode {:#04X}", op);
+
+        let mut cpu = Cpu::new();
+        cpu.memory[0x0100..0x0103].copy_from_slice(&[op, 0x00, 0x20]);
+        cpu.pc = 0x0100;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x0103, "tick {:#04X}", op);
+    }
+}
+
+#[test]
+fn test_operands_wrap_at_top_of_memory() {
+    let mut cpu = Cpu::new();
+    // LXI H,0x1234 with its operands split across the top of memory
+    cpu.memory[0xFFFE] = 0x21;
+    cpu.memory[0xFFFF] = 0x34;
+    cpu.memory[0x0000] = 0x12;
+    cpu.pc = 0xFFFE;
+
+    assert_eq!(cpu.read_opcode(), (0x21, 0x34, 0x12));
+    cpu.tick().unwrap();
+    assert_eq!(cpu.h, 0x12);
+    assert_eq!(cpu.l, 0x34);
+}
+
+#[test]
+fn test_next_instruction() {
+    let mut cpu = Cpu::new();
+    cpu.load_test_rom("MVI_HLT").unwrap();
+
+    let next = cpu.next_instruction();
+    assert_eq!(next.addr, 0x0000);
+    assert_eq!(next.bytes, vec![0x3E, 0x42]);
+    assert_eq!(next.text, "MVI A,0x42");
+
+    cpu.tick().unwrap();
+    let next = cpu.next_instruction();
+    assert_eq!(next.addr, 0x0002);
+    assert_eq!(next.bytes, vec![0x76]);
+    assert!(next.text.starts_with("HLT"));
+}
+
+#[test]
+fn test_break_on_stack_underflow() {
+    // PUSH B; POP D; POP H; HLT
+    let program = [0xC5, 0xD1, 0xE1, 0x76];
+
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.sp = 0x2400;
+    cpu.set_break_on_stack_underflow(true);
+
+    assert_eq!(
+        cpu.run_quantum(100).unwrap(),
+        RunStopReason::StackError { sp: 0x2402 }
+    );
+    assert_eq!(cpu.pc, 0x0003);
+
+    // Setting up the stack with LXI SP isn't an underflow; popping past the
+    // new stack is
+    // LXI SP,0x2400; PUSH B; POP D; POP H; HLT
+    let program = [0x31, 0x00, 0x24, 0xC5, 0xD1, 0xE1, 0x76];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_break_on_stack_underflow(true);
+    assert_eq!(
+        cpu.run_quantum(100).unwrap(),
+        RunStopReason::StackError { sp: 0x2402 }
+    );
+    assert_eq!(cpu.pc, 0x0006);
+
+    // Without the check the same program runs through to HLT
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    assert_eq!(cpu.run_quantum(100).unwrap(), RunStopReason::Halted);
+}
+
+#[test]
+fn test_stack_underflow_from_reset_sp() {
+    // With SP left at its reset value of 0, pushes wrap down to 0xFFFE
+    // CALL 0x0010; HLT; at 0x0010: CALL 0x0020; RET; at 0x0020: RET
+    let mut cpu = Cpu::new();
+    cpu.memory[0x0000..0x0004].copy_from_slice(&[0xCD, 0x10, 0x00, 0x76]);
+    cpu.memory[0x0010..0x0014].copy_from_slice(&[0xCD, 0x20, 0x00, 0xC9]);
+    cpu.memory[0x0020] = 0xC9;
+    cpu.set_break_on_stack_underflow(true);
+    assert_eq!(cpu.run_quantum(100).unwrap(), RunStopReason::Halted);
+    assert_eq!(cpu.sp, 0x0000);
+
+    // One RET too many still trips it, across the wrap back up to 0x0002
+    // CALL 0x0010; HLT; at 0x0010: POP B; RET
+    let mut cpu = Cpu::new();
+    cpu.memory[0x0000..0x0004].copy_from_slice(&[0xCD, 0x10, 0x00, 0x76]);
+    cpu.memory[0x0010..0x0012].copy_from_slice(&[0xC1, 0xC9]);
+    cpu.set_break_on_stack_underflow(true);
+    assert_eq!(
+        cpu.run_quantum(100).unwrap(),
+        RunStopReason::StackError { sp: 0x0002 }
+    );
+}
+
+#[test]
+fn test_stack_underflow_check_after_lxi_sp() {
+    // LXI SP,0x2400; PUSH B; POP D; LXI SP,0x2500; PUSH B; POP D; HLT
+    // Moving SP up with LXI SP is setup, not an underflow
+    let program = [
+        0x31, 0x00, 0x24, 0xC5, 0xD1, 0x31, 0x00, 0x25, 0xC5, 0xD1, 0x76,
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_break_on_stack_underflow(true);
+
+    assert_eq!(cpu.run_quantum(100).unwrap(), RunStopReason::Halted);
+    assert_eq!(cpu.sp, 0x2500);
+}
+
+#[test]
+fn test_stop_context() {
+    // PUSH B; POP D; POP H; HLT
+    let program = [0xC5, 0xD1, 0xE1, 0x76];
+
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.sp = 0x2400;
+    cpu.set_break_on_stack_underflow(true);
+    assert_eq!(cpu.stop_context(), None);
+
+    let reason = cpu.run_quantum(100).unwrap();
+    assert_eq!(
+        cpu.stop_context(),
+        Some(StopContext {
+            reason,
+            pc: 0x0003,
+            cycles: 3,
+        })
+    );
+
+    // A run which fails records the error, not the previous stop
+    cpu.memory[0x0003] = 0xCB;
+    cpu.nop = false;
+    cpu.set_break_on_stack_underflow(false);
+    assert!(cpu.run_quantum(100).is_err());
+    let context = cpu.stop_context().unwrap();
+    assert_eq!(
+        context.reason,
+        RunStopReason::Error(CpuError::UnknownOpcode(0xCB))
+    );
+    assert_eq!(context.pc, 0x0003);
+
+    // An unbounded run which hits the safety limit records that, not the budget
+    let mut cpu = Cpu::new();
+    cpu.memory[0..3].copy_from_slice(&[0xC3, 0x00, 0x00]);
+    cpu.set_safety_limit(10);
+    cpu.run_blocking(None).unwrap();
+    let context = cpu.stop_context().unwrap();
+    assert_eq!(context.reason, RunStopReason::SafetyLimit);
+    assert_eq!(context.cycles, 10);
+}
+
+#[test]
+fn test_executed_addresses() {
+    let mut cpu = Cpu::new();
+    assert!(cpu.executed_addresses().is_empty());
+
+    // MVI B,0x03; loop: DCR B; JNZ loop; HLT; then an unreached MVI A,0xFF
+    let program = [0x06, 0x03, 0x05, 0xC2, 0x02, 0x00, 0x76, 0x3E, 0xFF];
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_record_execution(true);
+    cpu.run_quantum(100).unwrap();
+
+    let executed: Vec<usize> = cpu
+        .executed_addresses()
+        .iter()
+        .enumerate()
+        .filter(|(_, &e)| e)
+        .map(|(a, _)| a)
+        .collect();
+    assert_eq!(executed, vec![0x0000, 0x0002, 0x0003, 0x0006]);
+
+    cpu.set_record_execution(false);
+    assert!(cpu.executed_addresses().is_empty());
+}
+
+#[test]
+fn test_in_out_without_device() {
+    // IN 0x01; OUT 0x03; HLT
+    let program = [0xDB, 0x01, 0xD3, 0x03, 0x76];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.a = 0x12;
+    let flags = cpu.flags;
+
+    assert_eq!(cpu.run_quantum(10).unwrap(), RunStopReason::Halted);
+    assert_eq!(cpu.a, 0xFF);
+    assert_eq!(cpu.flags, flags);
+
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_in_default(0x00);
+    cpu.a = 0x12;
+    cpu.run_quantum(10).unwrap();
+    assert_eq!(cpu.a, 0x00);
+}
+
+#[test]
+fn test_run_until_unknown() {
+    // MVI A,0x01; INR A; then 0xCB, which isn't implemented
+    let program = [0x3E, 0x01, 0x3C, 0xCB, 0x76];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.set_unknown_opcode_policy(UnknownPolicy::NopAndLog);
+
+    assert_eq!(cpu.run_until_unknown(100), Some((0x0003, 0xCB)));
+    assert_eq!(cpu.pc, 0x0003);
+    assert_eq!(cpu.a, 0x02);
+    assert!(cpu.unknown_opcodes().is_empty());
+
+    // A program that halts first finds nothing
+    let mut cpu = Cpu::new();
+    cpu.memory[0] = 0x76;
+    assert_eq!(cpu.run_until_unknown(100), None);
+}
+
+#[test]
+fn test_interrupts_serviced() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x2400;
+    assert_eq!(cpu.interrupts_serviced(), 0);
+
+    cpu.force_interrupt(1).unwrap();
+    cpu.force_interrupt(2).unwrap();
+    assert_eq!(cpu.interrupts_serviced(), 2);
+
+    // A bad vector isn't serviced, so isn't counted
+    assert!(cpu.force_interrupt(8).is_err());
+    assert_eq!(cpu.interrupts_serviced(), 2);
+}
+
+#[test]
+fn test_last_compare_ordering() {
+    use std::cmp::Ordering;
+
+    // (A, operand, expected), compared as signed bytes; CMP B then CPI
+    let cases = [
+        (0x40, 0x40, Ordering::Equal),
+        (0x10, 0x40, Ordering::Less),
+        (0x40, 0x10, Ordering::Greater),
+        (0x80, 0x40, Ordering::Less),    // -128 < 64
+        (0xFF, 0x01, Ordering::Less),    // -1 < 1
+        (0x01, 0xFF, Ordering::Greater), // 1 > -1
+    ];
+    for &(a, operand, expected) in &cases {
+        let mut cpu = Cpu::new();
+        // CMP B; CPI operand; NOP
+        cpu.memory[0..4].copy_from_slice(&[0xB8, 0xFE, operand, 0x00]);
+        cpu.a = a;
+        cpu.b = operand;
+
+        assert_eq!(cpu.last_compare_ordering(), None);
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.last_compare_ordering(),
+            Some(expected),
+            "CMP {:#04X}",
+            a
+        );
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.last_compare_ordering(),
+            Some(expected),
+            "CPI {:#04X}",
+            a
+        );
+        cpu.tick().unwrap();
+        assert_eq!(cpu.last_compare_ordering(), None);
+    }
+}
+
+#[test]
+fn test_load_rom_autostart() {
+    // A plain ROM starting with JMP 0x0010
+    let rom = [0xC3, 0x10, 0x00, 0x76];
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.load_rom_autostart(&rom, None), Ok(0x0000));
+    assert_eq!(cpu.pc, 0x0000);
+    assert_eq!(cpu.memory[0x0000], 0xC3);
+    assert_eq!(cpu.rom_len(), rom.len());
+
+    // A .COM style program: MVI C,0x09; LXI D,0x0109; CALL 0x0005 (BDOS)
+    let com = [0x0E, 0x09, 0x11, 0x09, 0x01, 0xCD, 0x05, 0x00];
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.load_rom_autostart(&com, None), Ok(0x0100));
+    assert_eq!(cpu.pc, 0x0100);
+    assert_eq!(cpu.memory[0x0100..0x0108], com);
+
+    // The guess can be overridden
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.load_rom_autostart(&com, Some(0x0000)), Ok(0x0000));
+    assert_eq!(cpu.memory[0x0005], 0xCD);
+
+    // A full 64K image starting with a BDOS call would run off the top at 0x0100
+    let mut image = vec![0x00; 0x1_0000];
+    image[0x03..0x06].copy_from_slice(&[0xCD, 0x05, 0x00]);
+    let mut cpu = Cpu::new();
+    assert!(cpu.load_rom_autostart(&image, None).is_err());
+    assert_eq!(cpu.pc, 0x0000);
+    assert_eq!(cpu.memory[0x0103], 0x00);
+}
+
+#[test]
+fn test_detect_start_only_looks_at_the_start() {
+    // JMP first wins, even with a BDOS call right after it
+    let rom = [0xC3, 0x06, 0x00, 0xCD, 0x05, 0x00, 0x76];
+    assert_eq!(Cpu::detect_start(&rom), 0x0000);
+
+    // CD 05 00 as data further into a plain ROM doesn't make it a .COM
+    let mut rom = vec![0x00; 0x40];
+    rom[0x20..0x23].copy_from_slice(&[0xCD, 0x05, 0x00]);
+    assert_eq!(Cpu::detect_start(&rom), 0x0000);
+    rom[0] = 0xC3;
+    assert_eq!(Cpu::detect_start(&rom), 0x0000);
+
+    assert_eq!(Cpu::detect_start(&[]), 0x0000);
+}
+
+#[test]
+fn test_cycles_until_next_instruction() {
+    let mut cpu = Cpu::new();
+    cpu.load_test_rom("COUNT_LOOP").unwrap();
+
+    while !cpu.nop {
+        let expected = cpu.cycles_until_next_instruction();
+        cpu.tick().unwrap();
+        assert_eq!(usize::from(expected), cpu.last_cycles);
+    }
+}
+
+#[test]
+fn test_display_sp_at_top_of_memory() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0xFFFF;
+    cpu.memory[0xFFFF] = 0x12;
+    cpu.memory[0x0000] = 0x34;
+
+    let text = cpu.to_string();
+    assert!(text.contains("sp $[0xFFFF]=0x12"));
+    assert!(text.contains("sp+1 $[0x0000]=0x34"));
+}
+
+#[test]
+fn test_value_breakpoint() {
+    // LXI H,0x2000; loop: INR M; JMP loop
+    let program = [0x21, 0x00, 0x20, 0x34, 0xC3, 0x03, 0x00];
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    cpu.add_value_breakpoint(0x2000, 0x05);
+
+    assert_eq!(
+        cpu.run_quantum(100).unwrap(),
+        RunStopReason::ValueBreakpoint {
+            addr: 0x2000,
+            value: 0x05
+        }
+    );
+    assert_eq!(cpu.memory[0x2000], 0x05);
+    assert_eq!(cpu.pc, 0x0004); // Stopped right after the INR M that wrote it
+
+    // Once cleared, the run carries on past it
+    cpu.clear_value_breakpoints();
+    assert_eq!(cpu.run_quantum(10).unwrap(), RunStopReason::BudgetExhausted);
+    assert_eq!(cpu.memory[0x2000], 0x0A);
+}
+
+#[test]
+fn test_last_branch_taken() {
+    let mut cpu = Cpu::new();
+    // JMP 0x0010; then at 0x0010: MOV B,C; JZ 0x0000; CALL 0x0020; at 0x0020: RET
+    cpu.memory[0x0000..0x0003].copy_from_slice(&[0xC3, 0x10, 0x00]);
+    cpu.memory[0x0010..0x0017].copy_from_slice(&[0x41, 0xCA, 0x00, 0x00, 0xCD, 0x20, 0x00]);
+    cpu.memory[0x0020] = 0xC9;
+    cpu.sp = 0x2400;
+    assert!(!cpu.last_branch_taken());
+
+    cpu.tick().unwrap(); // JMP
+    assert!(cpu.last_branch_taken());
+    cpu.tick().unwrap(); // MOV B,C
+    assert!(!cpu.last_branch_taken());
+    cpu.tick().unwrap(); // JZ, not taken
+    assert!(!cpu.last_branch_taken());
+    cpu.tick().unwrap(); // CALL
+    assert!(cpu.last_branch_taken());
+    cpu.tick().unwrap(); // RET
+    assert!(cpu.last_branch_taken());
+    assert_eq!(cpu.pc, 0x0017);
+}
+
+#[test]
+fn test_cycle_count_saturates() {
+    let mut cpu = Cpu::new();
+    cpu.cycle_count = usize::MAX - 1;
+
+    cpu.tick().unwrap();
+    assert_eq!(cpu.cycle_count, usize::MAX);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.cycle_count, usize::MAX);
+}
+
+#[test]
+fn test_classic_line() {
+    let snap = RegistersSnapshot {
+        pc: 0x0100,
+        sp: 0x23FF,
+        a: 0x42,
+        h: 0x20,
+        flags: 0b1101_0111,
+        ..RegistersSnapshot::default()
+    };
+    assert_eq!(
+        snap.classic_line(),
+        "A=42 BC=0000 DE=0000 HL=2000 SP=23FF PC=0100 F=SZ-A-P-C"
+    );
+
+    let snap = RegistersSnapshot {
+        b: 0x12,
+        c: 0x34,
+        flags: 0b0100_0010,
+        ..RegistersSnapshot::default()
+    };
+    assert_eq!(
+        snap.classic_line(),
+        "A=00 BC=1234 DE=0000 HL=0000 SP=0000 PC=0000 F=-Z------"
+    );
+}
+
+#[test]
+fn test_live_disassemble() {
+    let mut cpu = Cpu::new();
+    cpu.load_test_rom("COUNT_LOOP").unwrap();
+    cpu.run_quantum(3).unwrap();
+    assert!(cpu.take_live_disassembly().is_empty());
+
+    cpu.set_live_disassemble(8);
+    cpu.run_blocking(Some(4)).unwrap();
+    let lines = cpu.take_live_disassembly();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "0x0002  05        DCR B");
+    assert_eq!(lines[1], "0x0003  C2 02 00  JNZ 0x0002");
+
+    // Taking them empties the buffer
+    assert!(cpu.take_live_disassembly().is_empty());
+
+    // Past the depth only the latest lines are kept
+    cpu.set_live_disassemble(2);
+    cpu.run_blocking(Some(5)).unwrap();
+    let lines = cpu.take_live_disassembly();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "0x0003  C2 02 00  JNZ 0x0002");
+    assert_eq!(lines[1], "0x0002  05        DCR B");
+
+    // A depth of 0 turns it off
+    cpu.set_live_disassemble(0);
+    cpu.run_blocking(Some(4)).unwrap();
+    assert!(cpu.take_live_disassembly().is_empty());
+}
+
+#[test]
+fn test_run_until_port_write() {
+    // MVI A,0x11; OUT 0x02; MVI A,0x42; OUT 0x03; HLT
+    let program = [0x3E, 0x11, 0xD3, 0x02, 0x3E, 0x42, 0xD3, 0x03, 0x76];
+
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    assert_eq!(cpu.run_until_port_write(0x03, 100), Some(0x42));
+    assert_eq!(cpu.pc, 0x0008);
+
+    // A port that is never written runs on to HLT and gives None
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    assert_eq!(cpu.run_until_port_write(0x07, 100), None);
+    assert_eq!(cpu.nop, true);
+
+    // So does a write that comes after the budget runs out
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(&program);
+    assert_eq!(cpu.run_until_port_write(0x03, 3), None);
+}
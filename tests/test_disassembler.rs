@@ -0,0 +1,239 @@
+pub use lib::disassembler::*;
+pub use lib::*;
+
+#[test]
+fn test_get_opcode_size() {
+    assert_eq!(get_opcode_size(0x00), 1); // NOP
+    assert_eq!(get_opcode_size(0x3E), 2); // MVI A
+    assert_eq!(get_opcode_size(0xD3), 2); // OUT
+    assert_eq!(get_opcode_size(0x21), 3); // LXI H
+    assert_eq!(get_opcode_size(0xC3), 3); // JMP
+    assert_eq!(get_opcode_size(0xCD), 3); // CALL
+}
+
+// A jump over a single data byte should leave that byte as data
+#[test]
+fn test_trace_disassemble_skips_data() {
+    let mut cpu = Cpu::new();
+    // JMP 0x0004; DB 0xFF; HLT
+    cpu.memory[0..5].copy_from_slice(&[0xC3, 0x04, 0x00, 0xFF, 0x76]);
+
+    let lines = trace_disassemble(&cpu, 0x0000);
+    assert_eq!(lines.len(), 3);
+
+    assert_eq!(lines[0].addr, 0x0000);
+    assert!(lines[0].is_code);
+    assert_eq!(lines[0].bytes, vec![0xC3, 0x04, 0x00]);
+
+    assert_eq!(lines[1].addr, 0x0003);
+    assert!(!lines[1].is_code);
+    assert_eq!(lines[1].text, "DB 0xFF");
+
+    assert_eq!(lines[2].addr, 0x0004);
+    assert!(lines[2].is_code);
+}
+
+// Following a CALL should decode both the subroutine and the return path
+#[test]
+fn test_trace_disassemble_call() {
+    let mut cpu = Cpu::new();
+    // CALL 0x0005; HLT; DB 0xAA; RET
+    cpu.memory[0..6].copy_from_slice(&[0xCD, 0x05, 0x00, 0x76, 0xAA, 0xC9]);
+
+    let lines = trace_disassemble(&cpu, 0x0000);
+    let code: Vec<usize> = lines.iter().filter(|l| l.is_code).map(|l| l.addr).collect();
+    assert_eq!(code, vec![0x0000, 0x0003, 0x0005]);
+    assert!(!lines.iter().find(|l| l.addr == 0x0004).unwrap().is_code);
+}
+
+// A jump to itself and a PCHL must both terminate the trace
+#[test]
+fn test_trace_disassemble_stops() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0..3].copy_from_slice(&[0xC3, 0x00, 0x00]);
+    let lines = trace_disassemble(&cpu, 0x0000);
+    assert_eq!(lines.len(), 1);
+
+    cpu.memory[0..2].copy_from_slice(&[0xE9, 0x00]);
+    let lines = trace_disassemble(&cpu, 0x0000);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].bytes, vec![0xE9]);
+}
+
+#[test]
+fn test_get_opcode_kind() {
+    assert_eq!(get_opcode_kind(0x00), OpKind::Control); // NOP
+    assert_eq!(get_opcode_kind(0x76), OpKind::Control); // HLT
+    assert_eq!(get_opcode_kind(0x41), OpKind::DataTransfer); // MOV B,C
+    assert_eq!(get_opcode_kind(0x21), OpKind::DataTransfer); // LXI H
+    assert_eq!(get_opcode_kind(0x3A), OpKind::DataTransfer); // LDA
+    assert_eq!(get_opcode_kind(0x03), OpKind::Arithmetic); // INX B
+    assert_eq!(get_opcode_kind(0x09), OpKind::Arithmetic); // DAD B
+    assert_eq!(get_opcode_kind(0x27), OpKind::Arithmetic); // DAA
+    assert_eq!(get_opcode_kind(0x86), OpKind::Arithmetic); // ADD M
+    assert_eq!(get_opcode_kind(0xAF), OpKind::Logical); // XRA A
+    assert_eq!(get_opcode_kind(0xFE), OpKind::Logical); // CPI
+    assert_eq!(get_opcode_kind(0x07), OpKind::Logical); // RLC
+    assert_eq!(get_opcode_kind(0xC3), OpKind::Branch); // JMP
+    assert_eq!(get_opcode_kind(0xCD), OpKind::Branch); // CALL
+    assert_eq!(get_opcode_kind(0xC9), OpKind::Branch); // RET
+    assert_eq!(get_opcode_kind(0xFF), OpKind::Branch); // RST 7
+    assert_eq!(get_opcode_kind(0xC5), OpKind::Stack); // PUSH B
+    assert_eq!(get_opcode_kind(0xF1), OpKind::Stack); // POP PSW
+    assert_eq!(get_opcode_kind(0xD3), OpKind::IO); // OUT
+}
+
+#[test]
+fn test_flags_text() {
+    assert_eq!(flags_text(0b0000_0010), "--0-0-1-");
+    assert_eq!(flags_text(0b1101_0111), "SZ0A0P1C");
+    assert_eq!(flags_text(0b0100_0011), "-Z0-0-1C");
+}
+
+#[test]
+fn test_status_panel() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0..3].copy_from_slice(&[0x3E, 0x22, 0x76]); // MVI A, 0x22; HLT
+    cpu.set_flag(FLAG_ZERO);
+    cpu.tick().unwrap();
+
+    let panel = status_panel(&cpu);
+    assert!(panel.contains("PC:0x0002"));
+    assert!(panel.contains("INTS:0"));
+    assert!(panel.contains("A:0x22"));
+    assert!(panel.contains("FLAGS:-Z0-0-1-"));
+    assert!(panel.contains("LAST:MVI A"));
+    assert!(panel.contains("NEXT:HLT"));
+}
+
+#[test]
+fn test_get_opcode_cycles() {
+    assert_eq!(get_opcode_cycles(0x00), 4); // NOP
+    assert_eq!(get_opcode_cycles(0x03), 5); // INX B
+    assert_eq!(get_opcode_cycles(0x0B), 5); // DCX B
+    assert_eq!(get_opcode_cycles(0x09), 10); // DAD B
+    assert_eq!(get_opcode_cycles(0x7E), 7); // MOV A,M
+    assert_eq!(get_opcode_cycles(0x22), 16); // SHLD
+    assert_eq!(get_opcode_cycles(0xC9), 10); // RET
+    assert_eq!(get_opcode_cycles(0xCD), 17); // CALL
+    assert_eq!(get_opcode_cycles(0xE3), 18); // XTHL
+}
+
+#[test]
+fn test_write_trace() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0] = 0x00;
+    cpu.tick().unwrap();
+    let line = disassemble(&cpu, 0);
+
+    // Disassembly is off by default, so nothing is written
+    let mut out: Vec<u8> = Vec::new();
+    write_trace(&cpu, &line, &mut out).unwrap();
+    assert!(out.is_empty());
+
+    cpu.set_disassemble(true);
+    write_trace(&cpu, &line, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!("{}\n{}\n", HEADER, line)
+    );
+}
+
+// Every opcode has a sane size and cycle count, and the CPU and the
+// disassembler agree on which opcodes are unknown
+#[test]
+fn test_opcode_tables_all_opcodes() {
+    for op in 0..=0xFF_u8 {
+        let size = get_opcode_size(op);
+        assert!((1..=3).contains(&size), "{:#04X} has size {}", op, size);
+        assert!(get_opcode_cycles(op) >= 4, "{:#04X} has too few cycles", op);
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x2400;
+        let unknown = matches!(
+            cpu.run_opcode((op, 0x00, 0x00)),
+            Err(CpuError::UnknownOpcode(_))
+        );
+        let text = get_opcode_text((op, 0x00, 0x00)).to_string();
+        assert_eq!(unknown, text == "UNK", "{:#04X} is {}", op, text);
+    }
+
+    // A few well known entries from the datasheet, as (size, cycles)
+    let known = |op| (get_opcode_size(op), get_opcode_cycles(op));
+    assert_eq!(known(0xC3), (3, 10)); // JMP
+    assert_eq!(known(0x41), (1, 5)); // MOV B,C
+    assert_eq!(known(0x3A), (3, 13)); // LDA
+    assert_eq!(known(0x3E), (2, 7)); // MVI A
+    assert_eq!(known(0xCD), (3, 17)); // CALL
+}
+
+#[test]
+fn test_listing() {
+    let mut cpu = Cpu::new();
+    // LXI H,0x2010; MVI M,0x42; JMP 0x0000
+    let program = [0x21, 0x10, 0x20, 0x36, 0x42, 0xC3, 0x00, 0x00];
+    cpu.memory[..program.len()].copy_from_slice(&program);
+
+    let text = listing(&cpu, 0x0000, program.len());
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "0x0000  21 10 20  LXI H,0x2010");
+    assert_eq!(lines[1], "0x0003  36 42     MVI (HL),0x42");
+    assert_eq!(lines[2], "0x0005  C3 00 00  JMP 0x0000");
+
+    let path = std::env::temp_dir().join("eightyeighty_test_listing.txt");
+    let path = path.to_str().unwrap();
+    write_listing(&cpu, 0x0000, program.len(), path).unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap(), text);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_listing_operands() {
+    let mut cpu = Cpu::new();
+    // OUT 0x01; STA 0x2000; LDA 0x2000; LXI SP,0x2400; CNZ 0x0100; ADI 0x05
+    let program = [
+        0xD3, 0x01, 0x32, 0x00, 0x20, 0x3A, 0x00, 0x20, 0x31, 0x00, 0x24, 0xC4, 0x00, 0x01, 0xC6,
+        0x05,
+    ];
+    cpu.memory[..program.len()].copy_from_slice(&program);
+
+    let text: Vec<String> = trace_disassemble(&cpu, 0x0000)
+        .into_iter()
+        .map(|l| l.text)
+        .collect();
+    assert_eq!(
+        &text[..6],
+        [
+            "OUT 0x01",
+            "STA 0x2000",
+            "LDA 0x2000",
+            "LXI SP,0x2400",
+            "CNZ 0x0100",
+            "ADI 0x05",
+        ]
+    );
+}
+
+#[test]
+fn test_branch_classification() {
+    // (op, branch, call, return, unconditional)
+    let cases = [
+        (0xC3, true, false, false, true),   // JMP
+        (0xC2, true, false, false, false),  // JNZ
+        (0xCD, true, true, false, true),    // CALL
+        (0xC4, true, true, false, false),   // CNZ
+        (0xC9, true, false, true, true),    // RET
+        (0xC0, true, false, true, false),   // RNZ
+        (0xE9, true, false, false, true),   // PCHL
+        (0xCF, true, true, false, true),    // RST 1
+        (0x76, false, false, false, false), // HLT
+        (0x3E, false, false, false, false), // MVI A
+    ];
+    for &(op, branch, call, ret, uncond) in &cases {
+        assert_eq!(is_branch(op), branch, "{:#04X}", op);
+        assert_eq!(is_call(op), call, "{:#04X}", op);
+        assert_eq!(is_return(op), ret, "{:#04X}", op);
+        assert_eq!(is_unconditional(op), uncond, "{:#04X}", op);
+    }
+}
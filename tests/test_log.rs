@@ -0,0 +1,51 @@
+pub use lib::*;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+// Keeps every record logged, so tests can look at what went out. The logger
+// is global to the process, so this file holds the only test that sets it.
+struct CaptureLogger {
+    records: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn test_interrupt_logs_at_debug() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let mut cpu = Cpu::new();
+    cpu.pc = 0x1234;
+    cpu.sp = 0x2400;
+    cpu.force_interrupt(2).unwrap();
+
+    let records = LOGGER.records.lock().unwrap();
+    assert!(records
+        .iter()
+        .any(|(level, msg)| *level == Level::Debug && msg.contains("RST 2")));
+
+    // A refused vector isn't serviced, so nothing more is logged for it
+    let before = records.len();
+    drop(records);
+    assert!(cpu.force_interrupt(8).is_err());
+    assert_eq!(LOGGER.records.lock().unwrap().len(), before);
+}
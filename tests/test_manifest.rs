@@ -0,0 +1,68 @@
+pub use lib::manifest::*;
+pub use lib::*;
+
+#[test]
+fn test_parse() {
+    let text = r#"
+        # Two programs
+        [[program]]
+        name = "first"
+        load_addr = 0x0000
+        file = "first.bin"
+
+        [[program]]
+        name = "second"
+        load_addr = 4096
+        file = "sub dir/second program.bin"
+    "#;
+    assert_eq!(
+        parse(text).unwrap(),
+        vec![
+            ("first".to_string(), 0x0000, "first.bin".to_string()),
+            (
+                "second".to_string(),
+                0x1000,
+                "sub dir/second program.bin".to_string()
+            ),
+        ]
+    );
+    assert_eq!(parse("").unwrap(), vec![]);
+
+    // A missing field, a negative address and broken TOML are all refused
+    assert!(parse("[[program]]\nname = \"a\"\nload_addr = 0").is_err());
+    assert!(parse("[[program]]\nname = \"a\"\nload_addr = -1\nfile = \"a.bin\"").is_err());
+    assert!(parse("[[program]\nname = \"a\"").is_err());
+}
+
+#[test]
+fn test_read_and_load() {
+    // Named per process, so test runs going at the same time don't collide
+    let dir =
+        std::env::temp_dir().join(format!("eightyeighty_test_manifest_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.bin"), [0x3E, 0x42, 0x76]).unwrap();
+    std::fs::write(dir.join("b.bin"), [0xC9]).unwrap();
+    let manifest = dir.join("manifest.toml");
+    std::fs::write(
+        &manifest,
+        "[[program]]\nname = \"a\"\nload_addr = 0x0000\nfile = \"a.bin\"\n\
+         [[program]]\nname = \"b\"\nload_addr = 0x0100\nfile = \"b.bin\"\n",
+    )
+    .unwrap();
+
+    let segments = read(manifest.to_str().unwrap()).unwrap();
+    let mut cpu = Cpu::new();
+    cpu.load_manifest(&segments).unwrap();
+    assert_eq!(cpu.memory[0x0000..0x0003], [0x3E, 0x42, 0x76]);
+    assert_eq!(cpu.memory[0x0100], 0xC9);
+
+    // A segment hanging off the top of memory is refused
+    let too_big = vec![("c".to_string(), 0xFFFF, vec![0x00, 0x00])];
+    assert!(cpu.load_manifest(&too_big).is_err());
+
+    // As is one whose address is so large the end can't even be worked out
+    let huge = vec![("d".to_string(), usize::MAX, vec![0x00])];
+    assert!(cpu.load_manifest(&huge).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
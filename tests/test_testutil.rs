@@ -0,0 +1,72 @@
+pub use lib::testutil::*;
+pub use lib::*;
+
+// MVI B,0x03; loop: DCR B; JNZ loop; HLT
+const PROGRAM: [u8; 7] = [0x06, 0x03, 0x05, 0xC2, 0x02, 0x00, 0x76];
+
+fn capture(program: &[u8], count: usize) -> Vec<RegistersSnapshot> {
+    let mut cpu = Cpu::new();
+    cpu.memory[..program.len()].copy_from_slice(program);
+    (0..count)
+        .map(|_| {
+            cpu.tick().unwrap();
+            cpu.snapshot()
+        })
+        .collect()
+}
+
+#[test]
+fn test_replay_and_compare_self() {
+    let log = capture(&PROGRAM, 8);
+    assert_eq!(replay_and_compare(&PROGRAM, &log), Ok(()));
+}
+
+#[test]
+fn test_replay_and_compare_mismatch() {
+    let mut log = capture(&PROGRAM, 8);
+    log[1].b = 0x7F;
+
+    let mismatch = replay_and_compare(&PROGRAM, &log).unwrap_err();
+    assert_eq!(mismatch.index, 1);
+    assert_eq!(mismatch.diffs, vec!["b: expected 0x007F, got 0x0002"]);
+}
+
+#[test]
+fn test_find_divergence_self() {
+    // The reference is a second copy of our own machine, stepped in lockstep
+    let mut reference = Cpu::new();
+    reference.memory[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    let stepper = |index: usize| {
+        if index >= 8 {
+            return None;
+        }
+        reference.tick().unwrap();
+        Some(reference.snapshot())
+    };
+
+    assert_eq!(find_divergence(&PROGRAM, stepper), None);
+}
+
+#[test]
+fn test_find_divergence_mismatch() {
+    let mut log = capture(&PROGRAM, 8);
+    log[3].pc = 0x0006;
+
+    let (index, expected, actual) = find_divergence(&PROGRAM, |i| log.get(i).copied()).unwrap();
+    assert_eq!(index, 3);
+    assert_eq!(expected.pc, 0x0006);
+    assert_eq!(actual.pc, 0x0003);
+}
+
+#[test]
+fn test_run_program() {
+    // MVI A,0x05; MVI B,0x03; ADD B; HLT
+    let mut cpu = run_program(&[0x3E, 0x05, 0x06, 0x03, 0x80, 0x76]);
+    assert_eq!(cpu.a, 0x08);
+    assert_eq!(cpu.test_flag(FLAG_CARRY), false);
+    assert_eq!(cpu.nop, true);
+
+    // A program with no HLT stops at the limit
+    let cpu = run_program(&[0xC3, 0x00, 0x00]);
+    assert_eq!(cpu.cycle_count, RUN_PROGRAM_LIMIT);
+}